@@ -2,85 +2,81 @@ use std::process::Command;
 use criterion::{ Criterion, criterion_group, criterion_main };
 
 
-fn small_csv_owned() {
-    Command::new("target/release/rust_intro")
-        .args(&["resources/summary0.csv", "resources/txns0.csv", "--impl", "owned"])
+const SIZES: [(&str, &str, &str); 3] = [
+    ("small_csv", "resources/summary0.csv", "resources/txns0.csv"),
+    ("med_csv", "resources/summary1000.csv", "resources/txns1000.csv"),
+    ("large_csv", "resources/summary10000.csv", "resources/txns10000.csv"),
+];
+
+fn run_rust(summary: &str, txns: &str, strategy: &str) {
+    let output = Command::new("target/release/rust_intro")
+        .args(["validate", summary, txns, "--impl", strategy])
         .output()
-        .expect("Process exited non-zero");
+        .expect("Failed to run rust_intro");
+    assert!(output.status.success(), "rust_intro {} exited with {}", strategy, output.status);
 }
 
-fn med_csv_owned() {
-    Command::new("target/release/rust_intro")
-        .args(&["resources/summary1000.csv", "resources/txns1000.csv", "--impl", "owned"])
+fn run_python(summary: &str, txns: &str) {
+    let output = Command::new("python3")
+        .args(["resources/python_impl.py", summary, txns, "--impl", "owned"])
         .output()
-        .expect("Process exited non-zero");
+        .expect("Failed to run python3");
+    assert!(output.status.success(), "python_impl.py exited with {}", output.status);
 }
 
-fn large_csv_owned() {
-    Command::new("target/release/rust_intro")
-        .args(&["resources/summary100000.csv", "resources/txns100000.csv", "--impl", "owned"])
-        .output()
-        .expect("Process exited non-zero");
-}
-
-fn small_csv_refs() {
-    Command::new("target/release/rust_intro")
-        .args(&["resources/summary0.csv", "resources/txns0.csv", "--impl", "refs"])
-        .output()
-        .expect("Process exited non-zero");
-}
-
-fn py_small_csv_owned() {
-    Command::new("python3")
-        .args(&["resources/python_impl.py", "resources/summary0.csv", "resources/txns0.csv", "--impl", "owned"])
-        .output()
-        .expect("Process exited non-zero");
-}
-
-fn py_med_csv_owned() {
-    Command::new("python3")
-        .args(&["resources/python_impl.py", "resources/summary1000.csv", "resources/txns1000.csv", "--impl", "owned"])
-        .output()
-        .expect("Process exited non-zero");
-}
 
-fn py_large_csv_owned() {
-    Command::new("python3")
-        .args(&["resources/python_impl.py", "resources/summary100000.csv", "resources/txns100000.csv", "--impl", "owned"])
-        .output()
-        .expect("Process exited non-zero");
-}
-
-
-fn bench_small_csv_owned(c: &mut Criterion) {
-    c.bench_function("Rust::small_csv::owned", |b| b.iter(|| small_csv_owned()));
+/// Run each strategy as a process. The generated inputs aren't sorted, so
+/// strategies that need them to be are left out.
+fn bench_rust(c: &mut Criterion) {
+    for (size, summary, txns) in SIZES.iter() {
+        for strategy in rust_intro::strategies() {
+            if strategy.needs_sorted_input() {
+                continue;
+            }
+            let name = strategy.name();
+            c.bench_function(&format!("Rust::{}::{}", size, name), move |b| {
+                b.iter(|| run_rust(summary, txns, name))
+            });
+        }
+    }
 }
 
-fn bench_med_csv_owned(c: &mut Criterion) {
-    c.bench_function("Rust::med_csv::owned", |b| b.iter(|| med_csv_owned()));
+/// Run each strategy in process on the 10k summary, leaving out process
+/// startup, to compare the strategies themselves. Run them with
+/// `cargo bench --bench benchamrks -- InProcess`.
+fn bench_in_process(c: &mut Criterion) {
+    for strategy in rust_intro::strategies() {
+        if strategy.needs_sorted_input() {
+            continue;
+        }
+        c.bench_function(&format!("InProcess::10k_csv::{}", strategy.name()), move |b| {
+            b.iter(|| {
+                strategy
+                    .report(
+                        "resources/summary10000.csv".into(),
+                        "resources/txns10000.csv".into(),
+                        &rust_intro::Options::default(),
+                    )
+                    .expect("Validation failed")
+            })
+        });
+    }
 }
 
-fn bench_large_csv_owned(c: &mut Criterion) {
-    c.bench_function("Rust::large_csv::owned", |b| b.iter(|| large_csv_owned()));
+fn bench_python(c: &mut Criterion) {
+    for (size, summary, txns) in SIZES.iter() {
+        c.bench_function(&format!("Python::{}", size), move |b| {
+            b.iter(|| run_python(summary, txns))
+        });
+    }
 }
 
-fn bench_small_csv_refs(c: &mut Criterion) {
-    c.bench_function("Rust::small_csv::refs", |b| b.iter(|| small_csv_refs()));
-}
 
-fn bench_py_small_csv_owned(c: &mut Criterion) {
-    c.bench_function("Python::small_csv", |b| b.iter(|| py_small_csv_owned()));
+criterion_group!(rust, bench_rust);
+criterion_group! {
+    name = in_process;
+    config = Criterion::default().sample_size(10);
+    targets = bench_in_process
 }
-
-fn bench_py_med_csv_owned(c: &mut Criterion) {
-    c.bench_function("Python::med_csv", |b| b.iter(|| py_med_csv_owned()));
-}
-
-fn bench_py_large_csv_owned(c: &mut Criterion) {
-    c.bench_function("Python::small_csv", |b| b.iter(|| py_large_csv_owned()));
-}
-
-
-criterion_group!(rust, bench_small_csv_owned, bench_med_csv_owned, bench_large_csv_owned);
-criterion_group!(python, bench_py_small_csv_owned, bench_py_med_csv_owned, bench_py_large_csv_owned);
-criterion_main!(rust, python);
+criterion_group!(python, bench_python);
+criterion_main!(rust, in_process, python);
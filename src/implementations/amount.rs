@@ -0,0 +1,227 @@
+//! # Amounts
+//!
+//! Amounts are held as whole numbers of a currency's minor unit, so that
+//! they compare and add up exactly. Partners send them as decimal strings
+//! such as `12.50` instead, and how many decimal places a currency has
+//! varies, so each is given a scale: with a scale of 2, `12.50` is read as
+//! 1250.
+//!
+//! Amounts on rows without a currency, or in a currency without a scale of
+//! its own, use the default scale, which is 0 unless set. The ledger and
+//! summary are read with the same scales, so both sides of a comparison
+//! are in the same unit.
+//!
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// The largest scale an amount can be read with, as `10^18` is the largest
+/// power of ten that fits in an `i64`.
+pub const MAX_SCALE: u32 = 18;
+
+/// How many decimal places each currency's amounts have.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Scales {
+    default: u32,
+    currencies: HashMap<String, u32>,
+}
+
+impl Scales {
+    /// Read amounts with no scale of their own to `scale` decimal places.
+    pub fn set_default(&mut self, scale: u32) {
+        self.default = scale.min(MAX_SCALE);
+    }
+
+    /// Read amounts in `currency` to `scale` decimal places.
+    pub fn set<C: Into<String>>(&mut self, currency: C, scale: u32) {
+        self.currencies
+            .insert(currency.into(), scale.min(MAX_SCALE));
+    }
+
+    /// The scale of `currency`, which is empty for rows without one.
+    pub fn scale(&self, currency: &str) -> u32 {
+        self.currencies
+            .get(currency)
+            .copied()
+            .unwrap_or(self.default)
+    }
+
+    /// Parse `value` as an amount in `currency`, in its minor unit.
+    ///
+    /// Returns `None` if it isn't a decimal number, has more decimal places
+    /// than the scale allows, or doesn't fit in an `i64`. Extra places are
+    /// fine so long as they're zeros, since nothing is lost dropping them.
+    pub fn parse(&self, value: &str, currency: &str) -> Option<i64> {
+        parse_decimal(value, self.scale(currency))
+    }
+}
+
+/// Parse `value` as a decimal number of `10^-scale` units.
+pub(super) fn parse_decimal(value: &str, scale: u32) -> Option<i64> {
+    let (negative, digits) = match value.as_bytes().first()? {
+        b'-' => (true, &value[1..]),
+        b'+' => (false, &value[1..]),
+        _ => (false, value),
+    };
+    let (whole, fraction) = match digits.find('.') {
+        Some(dot) => (&digits[..dot], &digits[dot + 1..]),
+        None => (digits, ""),
+    };
+    let all_digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+    if whole.is_empty() && fraction.is_empty() || !all_digits(whole) || !all_digits(fraction) {
+        return None;
+    }
+    let (kept, dropped) = fraction.split_at(fraction.len().min(scale as usize));
+    if dropped.bytes().any(|byte| byte != b'0') {
+        return None;
+    }
+    let mut units: i128 = 0;
+    for byte in whole.bytes().chain(kept.bytes()) {
+        units = units
+            .checked_mul(10)?
+            .checked_add(i128::from(byte - b'0'))?;
+    }
+    units = units.checked_mul(10i128.pow(scale - kept.len() as u32))?;
+    if negative {
+        units = -units;
+    }
+    if units < i128::from(i64::MIN) || units > i128::from(i64::MAX) {
+        return None;
+    }
+    Some(units as i64)
+}
+
+/// An amount in minor units, and the currency it's in. The currency is
+/// empty if the amount's file has no `currency` column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Money<'a> {
+    pub amount: i64,
+    pub currency: &'a str,
+}
+
+/// The running total of a transaction's legs, which should come to zero.
+///
+/// Legs in different currencies can't be added up without converting them,
+/// so a transaction that mixes currencies is never unbalanced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Total {
+    currency: String,
+    mixed: bool,
+    /// `None` once the sum has overflowed
+    sum: Option<i64>,
+}
+
+impl Total {
+    pub fn new(currency: &str) -> Self {
+        Total {
+            currency: currency.to_owned(),
+            mixed: false,
+            sum: Some(0),
+        }
+    }
+
+    pub fn add(&mut self, leg: Money) {
+        self.mixed |= leg.currency != self.currency;
+        self.sum = self.sum.and_then(|sum| sum.checked_add(leg.amount));
+    }
+
+    /// What the legs came to if that isn't zero, or `Some(None)` if their
+    /// sum overflowed.
+    pub fn unbalanced(&self) -> Option<Option<i64>> {
+        match self.sum {
+            Some(0) => None,
+            _ if self.mixed => None,
+            sum => Some(sum),
+        }
+    }
+}
+
+/// How far a ledger amount may be from the summary's before it's an error.
+///
+/// Parsed from a number of minor units, such as `2`, or a percentage of
+/// the summary amount, such as `0.1%`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tolerance {
+    /// Up to this many minor units either way
+    Absolute(u64),
+    /// Up to this fraction of the expected amount either way
+    Relative(f64),
+}
+
+impl Tolerance {
+    /// Whether `actual` is close enough to `expected` to let through.
+    pub fn allows(self, expected: i64, actual: i64) -> bool {
+        let difference = (i128::from(actual) - i128::from(expected)).abs();
+        match self {
+            Tolerance::Absolute(units) => difference <= i128::from(units),
+            Tolerance::Relative(fraction) => {
+                difference as f64 <= fraction * (expected as f64).abs()
+            }
+        }
+    }
+}
+
+impl FromStr for Tolerance {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "expected a number of minor units or a percentage, got `{}`",
+                value
+            )
+        };
+        match value.strip_suffix('%') {
+            Some(percent) => match percent.parse::<f64>() {
+                Ok(percent) if percent.is_finite() && percent >= 0.0 => {
+                    Ok(Tolerance::Relative(percent / 100.0))
+                }
+                _ => Err(invalid()),
+            },
+            None => value
+                .parse()
+                .map(Tolerance::Absolute)
+                .map_err(|_| invalid()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decimals_are_read_in_minor_units() {
+        let mut scales = Scales::default();
+        scales.set("USD", 2);
+        scales.set("JPY", 0);
+        assert_eq!(scales.parse("12.50", "USD"), Some(1250));
+        assert_eq!(scales.parse("-0.5", "USD"), Some(-50));
+        assert_eq!(scales.parse("7", "USD"), Some(700));
+        assert_eq!(scales.parse("1200", "JPY"), Some(1200));
+        assert_eq!(scales.parse("1200.00", "JPY"), Some(1200));
+        assert_eq!(scales.parse("12.345", "USD"), None);
+        assert_eq!(scales.parse("12.5", "JPY"), None);
+        assert_eq!(scales.parse("12.50", ""), None);
+        assert_eq!(scales.parse("-", "USD"), None);
+        assert_eq!(scales.parse("1e3", "USD"), None);
+        assert_eq!(scales.parse("92233720368547758.08", "USD"), None);
+        assert_eq!(scales.parse("-92233720368547758.08", "USD"), Some(i64::MIN));
+    }
+
+    #[test]
+    fn tolerances_are_absolute_or_relative() {
+        let absolute: Tolerance = "2".parse().unwrap();
+        assert_eq!(absolute, Tolerance::Absolute(2));
+        assert!(absolute.allows(100, 98) && absolute.allows(-100, -102));
+        assert!(!absolute.allows(100, 103));
+
+        let relative: Tolerance = "1%".parse().unwrap();
+        assert!(relative.allows(-1000, -990));
+        assert!(!relative.allows(-1000, -989));
+        assert!(!relative.allows(0, 1));
+
+        assert!("1.5".parse::<Tolerance>().is_err());
+        assert!("-1%".parse::<Tolerance>().is_err());
+    }
+}
@@ -0,0 +1,416 @@
+//! # Column Mapping
+//!
+//! Both inputs are read by header name rather than by position, so exports
+//! that reorder their columns or add extra ones still load. Where a partner
+//! names a column differently, [`Columns`] maps the name the validators
+//! expect onto the one in the file.
+//!
+//! A mapping can also be kept in a CSV file of its own, with one rename per
+//! row:
+//!
+//! ```text
+//! input,column,header
+//! txns,amount,value_cents
+//! summary,id,transfer_id
+//! ```
+//!
+//! Amounts are read with the [`Scales`] their [`Layout`] is given, in the
+//! currency named by the row's `currency` column if the file has one.
+//! Dates are read as the calendar date at the start of the `date` column,
+//! which may hold a full timestamp.
+//!
+
+use csv::StringRecord;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use super::amount::Scales;
+use super::dates::calendar_date;
+use super::error::{field, line_of, parse_field, Error, Location};
+use super::input::Input;
+
+/// The columns read from the transactions ledger.
+pub const TXN_COLUMNS: [&str; 4] = ["account", "txn_id", "amount", "balance"];
+/// The columns read from a transaction summary with one row per transfer.
+pub const SUMMARY_COLUMNS: [&str; 4] = ["id", "from", "to", "amount"];
+/// The columns read from a transaction summary with one row per leg. The
+/// legs of a transaction share its `id`, and their amounts are signed. Each
+/// leg must be on a different account: legs aren't summed per account.
+pub const LEG_COLUMNS: [&str; 3] = ["id", "account", "amount"];
+/// Columns read from either input when its header has them.
+pub const OPTIONAL_COLUMNS: [&str; 2] = ["currency", "date"];
+/// Every summary column that can be renamed, in either shape.
+pub const SUMMARY_RENAMES: [&str; 7] =
+    ["id", "from", "to", "amount", "account", "currency", "date"];
+/// Every ledger column that can be renamed.
+pub const TXN_RENAMES: [&str; 6] = ["account", "txn_id", "amount", "balance", "currency", "date"];
+const MAPPING_COLUMNS: [&str; 3] = ["input", "column", "header"];
+
+/// Header names for one input, keyed by the column they stand in for,
+/// along with the delimiter its fields are separated by.
+///
+/// Columns that haven't been renamed are looked up under their own name.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Columns {
+    renames: HashMap<String, String>,
+    /// A comma unless set otherwise
+    delimiter: Option<u8>,
+}
+
+impl Columns {
+    /// Split fields on `delimiter` rather than on commas.
+    pub fn set_delimiter(&mut self, delimiter: u8) {
+        self.delimiter = Some(delimiter);
+    }
+
+    /// The byte fields are split on.
+    pub fn delimiter(&self) -> u8 {
+        self.delimiter.unwrap_or(b',')
+    }
+
+    /// Read `column` from the header named `header`.
+    pub fn rename<C: Into<String>, H: Into<String>>(&mut self, column: C, header: H) {
+        self.renames.insert(column.into(), header.into());
+    }
+
+    /// Take every rename and the delimiter set in `other`, over any already
+    /// set here.
+    pub fn merge(&mut self, other: Columns) {
+        self.renames.extend(other.renames);
+        if other.delimiter.is_some() {
+            self.delimiter = other.delimiter;
+        }
+    }
+
+    /// The header that `column` is read from.
+    pub fn header<'a>(&'a self, column: &'a str) -> &'a str {
+        self.renames.get(column).map_or(column, String::as_str)
+    }
+
+    /// Find each of `columns` in a header row, along with any of the
+    /// [`OPTIONAL_COLUMNS`] it has.
+    ///
+    /// Fails with [`Error::MissingColumn`] for the first one that isn't
+    /// there; the file name is left for the caller to fill in with
+    /// [`Error::in_file`].
+    pub fn resolve(
+        &self,
+        headers: &StringRecord,
+        columns: &[&'static str],
+    ) -> Result<Layout, Error> {
+        let mut resolved = columns
+            .iter()
+            .map(|name| {
+                let header = self.header(name);
+                match headers.iter().position(|h| h == header) {
+                    Some(index) => Ok(Column {
+                        name,
+                        header: header.to_owned(),
+                        index,
+                    }),
+                    None => Err(Error::MissingColumn {
+                        file: Default::default(),
+                        column: (*name).to_owned(),
+                        header: header.to_owned(),
+                    }),
+                }
+            })
+            .collect::<Result<Vec<Column>, Error>>()?;
+        for name in OPTIONAL_COLUMNS
+            .iter()
+            .filter(|name| !columns.contains(name))
+        {
+            let header = self.header(name);
+            if let Some(index) = headers.iter().position(|h| h == header) {
+                resolved.push(Column {
+                    name,
+                    header: header.to_owned(),
+                    index,
+                });
+            }
+        }
+        Ok(Layout {
+            columns: resolved,
+            scales: Scales::default(),
+        })
+    }
+
+    /// Find the columns of a summary in a header row, whichever shape it
+    /// has. A header with an `account` column and no `from` column has one
+    /// row per leg; any other has one row per transfer.
+    pub fn resolve_summary(&self, headers: &StringRecord) -> Result<Layout, Error> {
+        let has = |column| headers.iter().any(|h| h == self.header(column));
+        if has("account") && !has("from") {
+            self.resolve(headers, &LEG_COLUMNS)
+        } else {
+            self.resolve(headers, &SUMMARY_COLUMNS)
+        }
+    }
+}
+
+/// Column renames for both inputs.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Mapping {
+    pub summary: Columns,
+    pub txns: Columns,
+}
+
+impl Mapping {
+    /// Load renames from a mapping file, as described in the module docs.
+    ///
+    /// Unknown inputs or columns are reported as [`Error::Parse`] errors
+    /// pointing at the offending row.
+    pub fn load<I: Into<Input>>(input: I) -> Result<Self, Error> {
+        let input = input.into();
+        let path = input.name().to_owned();
+        let mut reader = input.open()?;
+        let headers = reader.headers().map_err(|e| Error::csv(&path, e))?;
+        let layout = Columns::default()
+            .resolve(headers, &MAPPING_COLUMNS)
+            .map_err(|e| e.in_file(&path))?;
+        let mut mapping = Mapping::default();
+        for row in reader.records() {
+            let row = row.map_err(|e| Error::csv(&path, e))?;
+            let (columns, known) = match layout.field(&row, "input") {
+                Ok("summary") => (&mut mapping.summary, &SUMMARY_RENAMES[..]),
+                Ok("txns") => (&mut mapping.txns, &TXN_RENAMES[..]),
+                Ok(other) => return Err(unknown(&row, "input", other).in_file(&path)),
+                Err(e) => return Err(e.in_file(&path)),
+            };
+            let column = layout.field(&row, "column").map_err(|e| e.in_file(&path))?;
+            if !known.contains(&column) {
+                return Err(unknown(&row, "column", column).in_file(&path));
+            }
+            let header = layout.field(&row, "header").map_err(|e| e.in_file(&path))?;
+            columns.rename(column, header);
+        }
+        Ok(mapping)
+    }
+}
+
+fn unknown(row: &StringRecord, column: &str, value: &str) -> Error {
+    bad_value(row, column, value)
+}
+
+/// An [`Error::Parse`] for `value` in `column` of `row`.
+fn bad_value(row: &StringRecord, column: &str, value: &str) -> Error {
+    Error::Parse {
+        location: Location {
+            file: Default::default(),
+            line: line_of(row),
+            column: column.to_owned(),
+        },
+        value: value.to_owned(),
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Column {
+    name: &'static str,
+    header: String,
+    index: usize,
+}
+
+/// Where each column sits in one particular file, found from its header.
+#[derive(Debug, Clone)]
+pub struct Layout {
+    columns: Vec<Column>,
+    scales: Scales,
+}
+
+impl Layout {
+    /// Read amounts with `scales` rather than as whole numbers.
+    pub fn with_scales(mut self, scales: &Scales) -> Self {
+        self.scales = scales.clone();
+        self
+    }
+
+    fn column(&self, name: &str) -> &Column {
+        self.columns
+            .iter()
+            .find(|column| column.name == name)
+            .unwrap_or_else(|| panic!("column `{}` was not resolved", name))
+    }
+
+    /// Where `column` sits in each row.
+    pub fn index(&self, column: &str) -> usize {
+        self.column(column).index
+    }
+
+    /// Whether `column` was resolved, e.g. to tell which shape a summary
+    /// has.
+    pub fn has(&self, column: &str) -> bool {
+        self.columns.iter().any(|resolved| resolved.name == column)
+    }
+
+    /// The value of `column` in `row`. Errors name the header it was read
+    /// from, since that's what appears in the file.
+    pub fn field<'r>(&self, row: &'r StringRecord, column: &str) -> Result<&'r str, Error> {
+        let column = self.column(column);
+        field(row, column.index, &column.header)
+    }
+
+    /// The value of `column` in `row`, or an empty string if the file has no
+    /// such column.
+    pub fn optional<'r>(&self, row: &'r StringRecord, column: &str) -> Result<&'r str, Error> {
+        match self.has(column) {
+            true => self.field(row, column),
+            false => Ok(""),
+        }
+    }
+
+    /// The `YYYY-MM-DD` date at the start of the row's `date` column, or an
+    /// empty string if the file has no such column or the row has no date.
+    pub fn date<'r>(&self, row: &'r StringRecord) -> Result<&'r str, Error> {
+        let value = self.optional(row, "date")?;
+        if value.is_empty() {
+            return Ok(value);
+        }
+        calendar_date(value).ok_or_else(|| Error::Parse {
+            location: Location {
+                file: Default::default(),
+                line: line_of(row),
+                column: self.column("date").header.clone(),
+            },
+            value: value.to_owned(),
+        })
+    }
+
+    /// The value of `column` in `row`, parsed as `T`.
+    pub fn parse<T: FromStr>(&self, row: &StringRecord, column: &str) -> Result<T, Error> {
+        let column = self.column(column);
+        parse_field(row, column.index, &column.header)
+    }
+
+    /// The value of `column` in `row`, parsed as an amount in the row's
+    /// currency.
+    pub fn amount(&self, row: &StringRecord, column: &str) -> Result<i64, Error> {
+        let currency = self.optional(row, "currency")?;
+        let column = self.column(column);
+        let value = field(row, column.index, &column.header)?;
+        self.scales
+            .parse(value, currency)
+            .ok_or_else(|| bad_value(row, &column.header, value))
+    }
+
+    /// `amount`, as read from `column` of `row`, negated for the debit leg
+    /// of a transfer. The most negative amount has no negation, so it fails
+    /// as a value that can't be parsed.
+    pub fn debit(&self, row: &StringRecord, column: &str, amount: i64) -> Result<i64, Error> {
+        amount.checked_neg().ok_or_else(|| {
+            let column = self.column(column);
+            bad_value(
+                row,
+                &column.header,
+                row.get(column.index).unwrap_or_default(),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn columns_are_found_by_header_name() {
+        let headers = StringRecord::from(vec!["memo", "amount", "txn_id", "account", "balance"]);
+        let row = StringRecord::from(vec!["hi", "-5", "1", "a", "95"]);
+        let layout = Columns::default().resolve(&headers, &TXN_COLUMNS).unwrap();
+        assert_eq!(layout.field(&row, "account").unwrap(), "a");
+        assert_eq!(layout.parse::<i64>(&row, "amount").unwrap(), -5);
+    }
+
+    #[test]
+    fn renamed_columns_are_read_from_their_header() {
+        let headers = StringRecord::from(vec!["account", "txn_id", "value_cents", "balance"]);
+        let mut columns = Columns::default();
+        columns.rename("amount", "value_cents");
+        let layout = columns.resolve(&headers, &TXN_COLUMNS).unwrap();
+        let row = StringRecord::from(vec!["a", "1", "x", "95"]);
+        match layout.parse::<i64>(&row, "amount") {
+            Err(Error::Parse { location, .. }) => assert_eq!(location.column, "value_cents"),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn missing_columns_are_named() {
+        let headers = StringRecord::from(vec!["account", "txn_id", "amount"]);
+        match Columns::default().resolve(&headers, &TXN_COLUMNS) {
+            Err(Error::MissingColumn { column, header, .. }) => {
+                assert_eq!(column, "balance");
+                assert_eq!(header, "balance");
+            }
+            other => panic!("expected a missing column, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn amounts_are_read_in_the_currency_of_their_row() {
+        let headers = StringRecord::from(vec!["account", "txn_id", "amount", "balance", "ccy"]);
+        let mut columns = Columns::default();
+        columns.rename("currency", "ccy");
+        let mut scales = Scales::default();
+        scales.set("USD", 2);
+        let layout = columns
+            .resolve(&headers, &TXN_COLUMNS)
+            .unwrap()
+            .with_scales(&scales);
+        let row = StringRecord::from(vec!["a", "1", "-12.50", "87.5", "USD"]);
+        assert_eq!(layout.amount(&row, "amount").unwrap(), -1250);
+        assert_eq!(layout.amount(&row, "balance").unwrap(), 8750);
+        let row = StringRecord::from(vec!["a", "1", "-12.50", "87.5", "JPY"]);
+        match layout.amount(&row, "amount") {
+            Err(Error::Parse { location, value }) => {
+                assert_eq!(
+                    (location.column.as_str(), value.as_str()),
+                    ("amount", "-12.50")
+                )
+            }
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn mappings_load_from_a_file() {
+        let mapping = Mapping::load(Input::reader(
+            "mapping.csv",
+            &b"input,column,header\ntxns,amount,value_cents\nsummary,id,transfer_id\n"[..],
+        ))
+        .unwrap();
+        assert_eq!(mapping.txns.header("amount"), "value_cents");
+        assert_eq!(mapping.summary.header("id"), "transfer_id");
+        assert_eq!(mapping.summary.header("amount"), "amount");
+
+        let bad = Mapping::load(Input::reader(
+            "mapping.csv",
+            &b"input,column,header\ntxns,amout,value_cents\n"[..],
+        ));
+        assert_eq!(
+            bad.unwrap_err().to_string(),
+            "mapping.csv:2: column `column`: could not parse \"amout\""
+        );
+    }
+
+    #[test]
+    fn dates_are_read_from_the_start_of_timestamps() {
+        let headers = StringRecord::from(vec!["account", "txn_id", "amount", "balance", "date"]);
+        let layout = Columns::default().resolve(&headers, &TXN_COLUMNS).unwrap();
+        let row = |date| StringRecord::from(vec!["a", "1", "5", "5", date]);
+        assert_eq!(
+            layout.date(&row("2024-01-02T09:30:00Z")).unwrap(),
+            "2024-01-02"
+        );
+        assert_eq!(layout.date(&row("")).unwrap(), "");
+        match layout.date(&row("2024-02-30")) {
+            Err(Error::Parse { location, value }) => {
+                assert_eq!(
+                    (location.column.as_str(), value.as_str()),
+                    ("date", "2024-02-30")
+                )
+            }
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,517 @@
+//! # Configuration Profiles
+//!
+//! Each partner's feeds differ: in what their columns are called, what
+//! separates their fields, how far amounts may drift, and which strategy
+//! suits their size. Rather than spelling all of that out on the command
+//! line every time, a TOML config file can keep a named profile for each
+//! of them:
+//!
+//! ```toml
+//! [profiles.partner_x]
+//! # One strategy, or a list of them to compare
+//! strategy = "refs"
+//!
+//! [profiles.partner_x.summary]
+//! delimiter = ";"
+//! # The columns a summary leg is matched on: id, from, to and account
+//! keys = { id = "transfer_ref" }
+//! # Any other column: amount, currency and date
+//! columns = { amount = "value" }
+//!
+//! [profiles.partner_x.transactions]
+//! delimiter = "\t"
+//! # The columns a ledger row is matched on: account and txn_id
+//! keys = { account = "acct_no", txn_id = "transfer_ref" }
+//! columns = { amount = "value", balance = "running_total" }
+//!
+//! [profiles.partner_x.matching]
+//! tolerance = "0.01%"         # or a number of minor units, e.g. 1
+//! settlement_days = 2
+//! duplicates = "warn"         # or "fail"
+//! check_balances = true
+//! scale = 2                   # decimal places, unless a currency says otherwise
+//! scales = { JPY = 0 }
+//! rates = "rates.csv"
+//!
+//! [profiles.partner_x.output]
+//! format = "json"             # or "text"
+//! all = true
+//! junit = "out/partner_x.xml"
+//! discrepancies = "out/partner_x.csv"
+//! ```
+//!
+//! Every section and key is optional. Paths are relative to the directory
+//! the config file is in.
+//!
+//! The whole file is checked when it's loaded, not only the profile in
+//! use, so a mistake in one profile is caught before it's needed. Errors
+//! name the offending key, e.g. `profiles.partner_x.matching.scale`.
+//!
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::value::Table as TomlTable;
+use toml::Value;
+
+use super::amount::{Tolerance, MAX_SCALE};
+use super::columns::{Columns, SUMMARY_RENAMES, TXN_RENAMES};
+use super::error::Error;
+use super::fx::Rates;
+use super::options::{DuplicatePolicy, Options};
+use super::output::Format;
+use super::validator::strategy;
+
+/// The summary columns a leg is matched on.
+const SUMMARY_KEYS: [&str; 4] = ["id", "from", "to", "account"];
+/// The ledger columns a row is matched on.
+const TXN_KEYS: [&str; 2] = ["account", "txn_id"];
+
+/// How a profile's results are written out.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Output {
+    pub format: Option<Format>,
+    /// Report every discrepancy instead of stopping at the first
+    pub all: bool,
+    /// Where to also write the findings as JUnit XML
+    pub junit: Option<PathBuf>,
+    /// Where to also write the findings as a CSV of discrepancies
+    pub discrepancies: Option<PathBuf>,
+}
+
+/// Everything a profile sets.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Profile {
+    pub options: Options,
+    /// The names of the strategies to run. Empty if the profile doesn't say
+    pub strategies: Vec<&'static str>,
+    pub output: Output,
+}
+
+/// A config file, as described in the module docs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    path: PathBuf,
+    profiles: BTreeMap<String, Profile>,
+}
+
+impl Config {
+    /// Load and check every profile in the config file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| Error::Config {
+            file: path.to_owned(),
+            key: String::new(),
+            message: e.to_string(),
+        })?;
+        Config::parse(path, &text)
+    }
+
+    /// Check every profile in `text`, reporting errors against `path`.
+    pub fn parse<P: AsRef<Path>>(path: P, text: &str) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let root = text.parse::<Value>().map_err(|e| Error::Config {
+            file: path.to_owned(),
+            key: String::new(),
+            message: e.to_string(),
+        })?;
+        let root = Table {
+            file: path,
+            key: String::new(),
+            table: root.as_table().expect("a TOML document is a table"),
+        };
+        root.only(&["profiles"])?;
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        let mut profiles = BTreeMap::new();
+        if let Some(table) = root.table("profiles")? {
+            for name in table.table.keys() {
+                let profile = table.table(name)?.expect("the key was just listed");
+                profiles.insert(name.clone(), profile.profile(base)?);
+            }
+        }
+        Ok(Config {
+            path: path.to_owned(),
+            profiles,
+        })
+    }
+
+    /// The profile called `name`.
+    pub fn profile(&self, name: &str) -> Result<&Profile, Error> {
+        self.profiles.get(name).ok_or_else(|| {
+            let known = self.profiles.keys().cloned().collect::<Vec<_>>();
+            Error::Config {
+                file: self.path.clone(),
+                key: format!("profiles.{}", name),
+                message: match known.is_empty() {
+                    true => "no such profile; the file has none".to_owned(),
+                    false => format!("no such profile, expected one of: {}", known.join(", ")),
+                },
+            }
+        })
+    }
+}
+
+/// A table in a config file, along with the dotted key it's found at.
+struct Table<'c> {
+    file: &'c Path,
+    key: String,
+    table: &'c TomlTable,
+}
+
+impl<'c> Table<'c> {
+    /// The dotted key of `key` within this table.
+    fn path(&self, key: &str) -> String {
+        match self.key.is_empty() {
+            true => key.to_owned(),
+            false => format!("{}.{}", self.key, key),
+        }
+    }
+
+    fn error<M: Into<String>>(&self, key: &str, message: M) -> Error {
+        Error::Config {
+            file: self.file.to_owned(),
+            key: self.path(key),
+            message: message.into(),
+        }
+    }
+
+    fn expected(&self, key: &str, what: &str, value: &Value) -> Error {
+        self.error(
+            key,
+            format!("expected {}, found {}", what, value.type_str()),
+        )
+    }
+
+    /// Fail on the first key that isn't one of `known`.
+    fn only(&self, known: &[&str]) -> Result<(), Error> {
+        match self.table.keys().find(|key| !known.contains(&key.as_str())) {
+            Some(key) => Err(self.error(
+                key,
+                format!("unknown key, expected one of: {}", known.join(", ")),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn table(&self, key: &str) -> Result<Option<Table<'c>>, Error> {
+        match self.table.get(key) {
+            None => Ok(None),
+            Some(Value::Table(table)) => Ok(Some(Table {
+                file: self.file,
+                key: self.path(key),
+                table,
+            })),
+            Some(other) => Err(self.expected(key, "a table", other)),
+        }
+    }
+
+    fn string(&self, key: &str) -> Result<Option<&'c str>, Error> {
+        match self.table.get(key) {
+            None => Ok(None),
+            Some(Value::String(value)) => Ok(Some(value)),
+            Some(other) => Err(self.expected(key, "a string", other)),
+        }
+    }
+
+    fn boolean(&self, key: &str) -> Result<Option<bool>, Error> {
+        match self.table.get(key) {
+            None => Ok(None),
+            Some(Value::Boolean(value)) => Ok(Some(*value)),
+            Some(other) => Err(self.expected(key, "true or false", other)),
+        }
+    }
+
+    /// An integer from 0 to `max`.
+    fn number(&self, key: &str, max: u32) -> Result<Option<u32>, Error> {
+        match self.table.get(key) {
+            None => Ok(None),
+            Some(Value::Integer(value)) if *value >= 0 && *value <= i64::from(max) => {
+                Ok(Some(*value as u32))
+            }
+            Some(Value::Integer(value)) => Err(self.error(
+                key,
+                format!("expected a number from 0 to {}, got {}", max, value),
+            )),
+            Some(other) => Err(self.expected(key, "a number", other)),
+        }
+    }
+
+    /// A path, relative to `base` unless it's absolute.
+    fn path_value(&self, key: &str, base: &Path) -> Result<Option<PathBuf>, Error> {
+        Ok(self.string(key)?.map(|path| base.join(path)))
+    }
+
+    fn profile(&self, base: &Path) -> Result<Profile, Error> {
+        self.only(&["strategy", "summary", "transactions", "matching", "output"])?;
+        let mut profile = Profile::default();
+        let names = match self.table.get("strategy") {
+            None => Vec::new(),
+            Some(Value::String(name)) => vec![(self.path("strategy"), name)],
+            Some(Value::Array(names)) => names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| match name {
+                    Value::String(name) => Ok((format!("{}[{}]", self.path("strategy"), i), name)),
+                    other => Err(self.expected("strategy", "a list of strategy names", other)),
+                })
+                .collect::<Result<_, _>>()?,
+            Some(other) => return Err(self.expected("strategy", "a strategy name", other)),
+        };
+        for (key, name) in names {
+            match strategy(name) {
+                Some(found) => profile.strategies.push(found.name()),
+                None => {
+                    return Err(Error::Config {
+                        file: self.file.to_owned(),
+                        key,
+                        message: format!("unknown strategy `{}`", name),
+                    })
+                }
+            }
+        }
+        let columns = &mut profile.options.columns;
+        if let Some(summary) = self.table("summary")? {
+            summary.input(&mut columns.summary, &SUMMARY_RENAMES, &SUMMARY_KEYS)?;
+        }
+        if let Some(txns) = self.table("transactions")? {
+            txns.input(&mut columns.txns, &TXN_RENAMES, &TXN_KEYS)?;
+        }
+        if let Some(matching) = self.table("matching")? {
+            matching.matching(&mut profile.options, base)?;
+        }
+        if let Some(output) = self.table("output")? {
+            profile.output = output.output(base)?;
+        }
+        Ok(profile)
+    }
+
+    /// Read an input's schema into `columns`. `renames` are the columns it
+    /// has, of which `keys` are the ones it's matched on.
+    fn input(&self, columns: &mut Columns, renames: &[&str], keys: &[&str]) -> Result<(), Error> {
+        self.only(&["delimiter", "columns", "keys"])?;
+        if let Some(delimiter) = self.string("delimiter")? {
+            match delimiter.as_bytes() {
+                [byte] if *byte != b'"' && *byte != b'\n' && *byte != b'\r' => {
+                    columns.set_delimiter(*byte)
+                }
+                _ => {
+                    return Err(self.error(
+                        "delimiter",
+                        format!(
+                            "expected a single ASCII character other than a quote or \
+                             newline, got {:?}",
+                            delimiter
+                        ),
+                    ))
+                }
+            }
+        }
+        let others = renames
+            .iter()
+            .filter(|column| !keys.contains(column))
+            .copied()
+            .collect::<Vec<_>>();
+        for (section, allowed, elsewhere) in
+            &[("columns", &others[..], "keys"), ("keys", keys, "columns")]
+        {
+            let table = match self.table(section)? {
+                Some(table) => table,
+                None => continue,
+            };
+            for (column, header) in table.table.iter() {
+                if !allowed.contains(&column.as_str()) {
+                    let message = match renames.contains(&column.as_str()) {
+                        true => format!("`{}` is set under `{}`", column, elsewhere),
+                        false => format!("unknown column, expected one of: {}", allowed.join(", ")),
+                    };
+                    return Err(table.error(column, message));
+                }
+                match header {
+                    Value::String(header) => columns.rename(column.as_str(), header.as_str()),
+                    other => return Err(table.expected(column, "a header name", other)),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn matching(&self, options: &mut Options, base: &Path) -> Result<(), Error> {
+        self.only(&[
+            "tolerance",
+            "settlement_days",
+            "duplicates",
+            "check_balances",
+            "scale",
+            "scales",
+            "rates",
+        ])?;
+        options.tolerance = match self.table.get("tolerance") {
+            None => None,
+            Some(Value::String(tolerance)) => Some(
+                tolerance
+                    .parse::<Tolerance>()
+                    .map_err(|e| self.error("tolerance", e))?,
+            ),
+            Some(Value::Integer(units)) if *units >= 0 => Some(Tolerance::Absolute(*units as u64)),
+            Some(other) => {
+                return Err(self.expected(
+                    "tolerance",
+                    "a number of minor units or a percentage",
+                    other,
+                ))
+            }
+        };
+        options.settlement_days = self.number("settlement_days", u32::MAX)?;
+        if let Some(duplicates) = self.string("duplicates")? {
+            options.duplicates = DuplicatePolicy::from_name(duplicates).ok_or_else(|| {
+                self.error(
+                    "duplicates",
+                    format!("expected one of: {}", DuplicatePolicy::NAMES.join(", ")),
+                )
+            })?;
+        }
+        options.check_balances = self.boolean("check_balances")?.unwrap_or_default();
+        if let Some(scale) = self.number("scale", MAX_SCALE)? {
+            options.scales.set_default(scale);
+        }
+        if let Some(scales) = self.table("scales")? {
+            for currency in scales.table.keys() {
+                let scale = scales.number(currency, MAX_SCALE)?;
+                options
+                    .scales
+                    .set(currency.as_str(), scale.expect("the key was just listed"));
+            }
+        }
+        if let Some(path) = self.path_value("rates", base)? {
+            options.rates = Rates::load(path).map_err(|e| self.error("rates", e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    fn output(&self, base: &Path) -> Result<Output, Error> {
+        self.only(&["format", "all", "junit", "discrepancies"])?;
+        let format = match self.string("format")? {
+            Some(name) => Some(Format::from_name(name).ok_or_else(|| {
+                self.error(
+                    "format",
+                    format!("expected one of: {}", Format::NAMES.join(", ")),
+                )
+            })?),
+            None => None,
+        };
+        Ok(Output {
+            format,
+            all: self.boolean("all")?.unwrap_or_default(),
+            junit: self.path_value("junit", base)?,
+            discrepancies: self.path_value("discrepancies", base)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        [profiles.partner_x]
+        strategy = ["refs", "owned"]
+
+        [profiles.partner_x.transactions]
+        delimiter = "\t"
+        keys = { txn_id = "ref" }
+        columns = { amount = "value" }
+
+        [profiles.partner_x.matching]
+        tolerance = "0.5%"
+        scale = 2
+        scales = { JPY = 0 }
+        duplicates = "warn"
+
+        [profiles.partner_x.output]
+        format = "json"
+        junit = "out/x.xml"
+
+        [profiles.plain]
+    "#;
+
+    fn error(text: &str) -> String {
+        Config::parse("feeds/rust_intro.toml", text)
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn profiles_set_options_and_output() {
+        let config = Config::parse("feeds/rust_intro.toml", CONFIG).unwrap();
+        let profile = config.profile("partner_x").unwrap();
+        assert_eq!(profile.strategies, vec!["refs", "owned"]);
+        let txns = &profile.options.columns.txns;
+        assert_eq!(txns.delimiter(), b'\t');
+        assert_eq!(txns.header("txn_id"), "ref");
+        assert_eq!(txns.header("amount"), "value");
+        assert_eq!(profile.options.columns.summary.delimiter(), b',');
+        assert_eq!(profile.options.tolerance, Some(Tolerance::Relative(0.005)));
+        assert_eq!(profile.options.scales.parse("1.5", "USD"), Some(150));
+        assert_eq!(profile.options.scales.parse("150", "JPY"), Some(150));
+        assert_eq!(profile.options.duplicates, DuplicatePolicy::Warn);
+        assert_eq!(profile.output.format, Some(Format::Json));
+        assert_eq!(profile.output.junit, Some(PathBuf::from("feeds/out/x.xml")));
+        assert_eq!(config.profile("plain").unwrap(), &Profile::default());
+        assert_eq!(
+            config.profile("partner_y").unwrap_err().to_string(),
+            "feeds/rust_intro.toml: `profiles.partner_y`: no such profile, \
+             expected one of: partner_x, plain"
+        );
+    }
+
+    #[test]
+    fn errors_name_the_offending_key() {
+        assert_eq!(
+            error("[profiles.a.matching]\nscale = 40\n"),
+            "feeds/rust_intro.toml: `profiles.a.matching.scale`: \
+             expected a number from 0 to 18, got 40"
+        );
+        assert_eq!(
+            error("[profiles.a.matching]\ntolerance = \"lots\"\n"),
+            "feeds/rust_intro.toml: `profiles.a.matching.tolerance`: \
+             expected a number of minor units or a percentage, got `lots`"
+        );
+        assert_eq!(
+            error("[profiles.a]\nstrategy = [\"owned\", \"fast\"]\n"),
+            "feeds/rust_intro.toml: `profiles.a.strategy[1]`: unknown strategy `fast`"
+        );
+        assert_eq!(
+            error("[profiles.a.summary]\ncolumns = { id = \"ref\" }\n"),
+            "feeds/rust_intro.toml: `profiles.a.summary.columns.id`: `id` is set under `keys`"
+        );
+        assert_eq!(
+            error("[profiles.a.transactions]\ndelimiter = \"||\"\n"),
+            "feeds/rust_intro.toml: `profiles.a.transactions.delimiter`: expected a single \
+             ASCII character other than a quote or newline, got \"||\""
+        );
+        assert_eq!(
+            error("[profiles.a.output]\nall = \"yes\"\n"),
+            "feeds/rust_intro.toml: `profiles.a.output.all`: expected true or false, found string"
+        );
+        assert_eq!(
+            error("[profiles.a.outptu]\n"),
+            "feeds/rust_intro.toml: `profiles.a.outptu`: unknown key, expected one of: \
+             strategy, summary, transactions, matching, output"
+        );
+        assert!(error("[profiles.a\n").starts_with("feeds/rust_intro.toml: "));
+        assert!(
+            error("[profiles.a.matching]\nrates = \"missing.csv\"\n").starts_with(
+                "feeds/rust_intro.toml: `profiles.a.matching.rates`: feeds/missing.csv: "
+            ),
+            "{}",
+            error("[profiles.a.matching]\nrates = \"missing.csv\"\n")
+        );
+        match Config::load("feeds/rust_intro.toml") {
+            Err(Error::Config { key, message, .. }) => {
+                assert_eq!(key, "");
+                assert!(!message.is_empty());
+            }
+            other => panic!("expected a config error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! # Dates
+//!
+//! Both inputs may carry an optional `date` column: the day a summary
+//! transfer was made, and the day a ledger row posted. Either may be a
+//! plain `YYYY-MM-DD` date or a timestamp that starts with one, such as
+//! `2024-01-02T09:30:00Z`. Only the calendar date is kept, as written,
+//! since settlement windows are counted in whole days.
+//!
+
+/// The `YYYY-MM-DD` date at the start of `value`, or `None` if it doesn't
+/// start with a valid date. A time may follow after a `T` or a space.
+pub fn calendar_date(value: &str) -> Option<&str> {
+    let date = value.get(..10)?;
+    match value.as_bytes().get(10) {
+        None | Some(b'T') | Some(b' ') => {}
+        Some(_) => return None,
+    }
+    day_number(date).map(|_| date)
+}
+
+/// The number of days from 1970-01-01 to a `YYYY-MM-DD` date, or `None` if
+/// it isn't one.
+pub fn day_number(date: &str) -> Option<i64> {
+    let bytes = date.as_bytes();
+    if bytes.len() != 10 || bytes[4] != b'-' || bytes[7] != b'-' {
+        return None;
+    }
+    let number = |part: &str| -> Option<i64> {
+        match part.bytes().all(|byte| byte.is_ascii_digit()) {
+            true => part.parse().ok(),
+            false => None,
+        }
+    };
+    let (year, month, day) = (
+        number(&date[..4])?,
+        number(&date[5..7])?,
+        number(&date[8..])?,
+    );
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => return None,
+    };
+    if day < 1 || day > days_in_month {
+        return None;
+    }
+    // Count from March, so that a leap day falls at the end of the year
+    let (year, month) = match month {
+        1 | 2 => (year - 1, month + 9),
+        _ => (year, month - 3),
+    };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    Some(era * 146_097 + day_of_era - 719_468)
+}
+
+/// How many days after `from` the date `to` is, or `None` if either isn't
+/// a `YYYY-MM-DD` date.
+pub fn days_between(from: &str, to: &str) -> Option<i64> {
+    Some(day_number(to)? - day_number(from)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_are_counted_in_days() {
+        assert_eq!(day_number("1970-01-01"), Some(0));
+        assert_eq!(day_number("2000-03-01"), Some(11_017));
+        assert_eq!(days_between("2024-02-28", "2024-03-01"), Some(2));
+        assert_eq!(days_between("2023-02-28", "2023-03-01"), Some(1));
+        assert_eq!(days_between("2024-01-10", "2023-12-31"), Some(-10));
+        assert_eq!(day_number("2023-02-29"), None);
+        assert_eq!(day_number("2024-13-01"), None);
+        assert_eq!(day_number("2024-1-01"), None);
+    }
+
+    #[test]
+    fn timestamps_are_read_as_their_date() {
+        assert_eq!(calendar_date("2024-01-02"), Some("2024-01-02"));
+        assert_eq!(calendar_date("2024-01-02T09:30:00Z"), Some("2024-01-02"));
+        assert_eq!(calendar_date("2024-01-02 09:30"), Some("2024-01-02"));
+        assert_eq!(calendar_date("2024-01-02x"), None);
+        assert_eq!(calendar_date("02/01/2024"), None);
+        assert_eq!(calendar_date(""), None);
+    }
+}
@@ -0,0 +1,251 @@
+//! # Validation Errors
+//!
+//! Everything that can stop a validation run, as one enum that callers can
+//! match on. Problems with the input files say exactly where they are, and
+//! reconciliation failures carry the [`Finding`] that caused them.
+//!
+
+use csv::StringRecord;
+use std::error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use super::report::{Category, Finding};
+
+/// Where in an input file a problem was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub file: PathBuf,
+    /// 1-based, counting the header
+    pub line: u64,
+    pub column: String,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: column `{}`",
+            self.file.display(),
+            self.line,
+            self.column
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The file could not be opened, or a row could not be read as CSV
+    Csv { file: PathBuf, source: csv::Error },
+    /// The header row has no column with this name. `header` is the name
+    /// that was looked for, which differs from `column` if it was renamed.
+    MissingColumn {
+        file: PathBuf,
+        column: String,
+        header: String,
+    },
+    /// A row has no value for a required column
+    MissingField(Location),
+    /// A column holds a value that couldn't be parsed
+    Parse { location: Location, value: String },
+    /// A summary leg has no matching ledger row
+    NotFound(Box<Finding>),
+    /// A ledger row disagrees with the summary leg that refers to it
+    Mismatch(Box<Finding>),
+    /// No exchange rate converts a summary leg into its ledger row's currency
+    MissingRate(Box<Finding>),
+    /// A ledger row posted outside its summary leg's settlement window
+    LateSettlement(Box<Finding>),
+    /// The legs of a summary transaction don't sum to zero
+    Unbalanced(Box<Finding>),
+    /// A ledger row that no summary leg refers to
+    Unreferenced(Box<Finding>),
+    /// An account's running balance doesn't follow from its postings
+    BalanceBreak(Box<Finding>),
+    /// Keys that appear on more than one row, one finding per occurrence
+    Duplicates(Vec<Finding>),
+    /// Leg rows that give a transaction more than one leg on the same
+    /// account, one finding per row
+    RepeatedLegs(Vec<Finding>),
+    /// A row breaks the sort order its input was expected to follow
+    OutOfOrder(Box<Finding>),
+    /// A config file isn't valid TOML, or `key` in it has a value that
+    /// can't be used. `key` is dotted, e.g. `profiles.a.matching.scale`, and
+    /// empty for syntax errors, whose message gives the line instead
+    Config {
+        file: PathBuf,
+        key: String,
+        message: String,
+    },
+    /// The reconciliation store could not be opened, written or queried
+    Store {
+        file: PathBuf,
+        source: rusqlite::Error,
+    },
+}
+
+impl Error {
+    pub fn csv<P: AsRef<Path>>(file: P, source: csv::Error) -> Self {
+        Error::Csv {
+            file: file.as_ref().to_owned(),
+            source,
+        }
+    }
+
+    pub fn store<P: AsRef<Path>>(file: P, source: rusqlite::Error) -> Self {
+        Error::Store {
+            file: file.as_ref().to_owned(),
+            source,
+        }
+    }
+
+    /// Fill in the file name for errors raised while parsing a row, which
+    /// only knows about the row itself.
+    pub fn in_file<P: AsRef<Path>>(mut self, file: P) -> Self {
+        match &mut self {
+            Error::MissingField(location) | Error::Parse { location, .. } => {
+                location.file = file.as_ref().to_owned();
+            }
+            Error::MissingColumn { file: missing, .. } => {
+                *missing = file.as_ref().to_owned();
+            }
+            _ => {}
+        }
+        self
+    }
+
+    /// The findings behind a failed reconciliation. Empty for errors that
+    /// stopped the inputs from being read at all.
+    pub fn findings(&self) -> &[Finding] {
+        match self {
+            Error::NotFound(finding)
+            | Error::Mismatch(finding)
+            | Error::MissingRate(finding)
+            | Error::LateSettlement(finding)
+            | Error::Unbalanced(finding)
+            | Error::Unreferenced(finding)
+            | Error::BalanceBreak(finding)
+            | Error::OutOfOrder(finding) => std::slice::from_ref(finding),
+            Error::Duplicates(findings) | Error::RepeatedLegs(findings) => findings,
+            _ => &[],
+        }
+    }
+}
+
+impl From<Finding> for Error {
+    fn from(finding: Finding) -> Self {
+        let category = finding.category;
+        let finding = Box::new(finding);
+        match category {
+            Category::Missing => Error::NotFound(finding),
+            Category::AmountMismatch => Error::Mismatch(finding),
+            Category::MissingRate => Error::MissingRate(finding),
+            Category::LateSettlement => Error::LateSettlement(finding),
+            Category::Unbalanced => Error::Unbalanced(finding),
+            Category::Orphan => Error::Unreferenced(finding),
+            Category::BalanceBreak => Error::BalanceBreak(finding),
+            Category::DuplicateTxn | Category::DuplicateSummaryId => {
+                Error::Duplicates(vec![*finding])
+            }
+            Category::RepeatedLeg => Error::RepeatedLegs(vec![*finding]),
+            Category::OutOfOrder => Error::OutOfOrder(finding),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Csv { file, source } => write!(f, "{}: {}", file.display(), source),
+            Error::Config { file, key, message } if key.is_empty() => {
+                write!(f, "{}: {}", file.display(), message)
+            }
+            Error::Config { file, key, message } => {
+                write!(f, "{}: `{}`: {}", file.display(), key, message)
+            }
+            Error::Store { file, source } => write!(f, "{}: {}", file.display(), source),
+            Error::MissingColumn {
+                file,
+                column,
+                header,
+            } => {
+                write!(
+                    f,
+                    "{}: no `{}` column in the header",
+                    file.display(),
+                    header
+                )?;
+                if column != header {
+                    write!(f, " (mapped from `{}`)", column)?;
+                }
+                Ok(())
+            }
+            Error::MissingField(location) => write!(f, "{}: missing value", location),
+            Error::Parse { location, value } => {
+                write!(f, "{}: could not parse {:?}", location, value)
+            }
+            Error::NotFound(finding) => write!(f, "Could not find txn: {}", finding),
+            Error::Mismatch(finding) => write!(f, "Txn mismatch: {}", finding),
+            Error::MissingRate(finding) => write!(f, "No exchange rate: {}", finding),
+            Error::LateSettlement(finding) => write!(f, "Late settlement: {}", finding),
+            Error::Unbalanced(finding) => write!(f, "Unbalanced txn: {}", finding),
+            Error::Unreferenced(finding) => write!(f, "Unreferenced txn: {}", finding),
+            Error::BalanceBreak(finding) => write!(f, "Balance break: {}", finding),
+            Error::OutOfOrder(finding) => write!(f, "Out of order: {}", finding),
+            Error::Duplicates(findings) => {
+                write!(f, "Duplicate keys found:")?;
+                for finding in findings.iter() {
+                    write!(f, "\n{}", finding)?;
+                }
+                Ok(())
+            }
+            Error::RepeatedLegs(findings) => {
+                write!(f, "Transactions with more than one leg on an account:")?;
+                for finding in findings.iter() {
+                    write!(f, "\n{}", finding)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Csv { source, .. } => Some(source),
+            Error::Store { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// The 1-based line a record starts on, counting the header.
+pub fn line_of(row: &StringRecord) -> u64 {
+    row.position().map_or(0, |pos| pos.line())
+}
+
+fn location(row: &StringRecord, column: &str) -> Location {
+    Location {
+        file: PathBuf::new(),
+        line: line_of(row),
+        column: column.to_owned(),
+    }
+}
+
+/// The value of the column at `index`, or a [`Error::MissingField`] naming it.
+pub fn field<'r>(row: &'r StringRecord, index: usize, column: &str) -> Result<&'r str, Error> {
+    row.get(index)
+        .ok_or_else(|| Error::MissingField(location(row, column)))
+}
+
+/// The value of the column at `index` parsed as `T`, or an error naming the
+/// column and the raw value.
+pub fn parse_field<T: FromStr>(row: &StringRecord, index: usize, column: &str) -> Result<T, Error> {
+    let value = field(row, index, column)?;
+    value.parse::<T>().map_err(|_| Error::Parse {
+        location: location(row, column),
+        value: value.to_owned(),
+    })
+}
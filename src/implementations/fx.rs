@@ -0,0 +1,258 @@
+//! # Exchange Rates
+//!
+//! A cross-border transfer debits one account in one currency and credits
+//! another in a different one, so its legs can't be compared with the
+//! ledger as they are. When a leg and its ledger row name different
+//! currencies, the leg's amount is converted at the rate for the summary
+//! row's `date` before the two are compared.
+//!
+//! Rates are read from a CSV file with one rate per row, where a `pair` of
+//! `EUR/USD` and a `rate` of `1.085` mean that one euro buys 1.085 dollars:
+//!
+//! ```text
+//! date,pair,rate
+//! 2024-01-02,EUR/USD,1.085
+//! 2024-01-03,EUR/USD,1.0912
+//! ```
+//!
+//! The rate used for a date is the latest one on or before it, and the
+//! inverse of a pair's rate is used to convert the other way. Dates are
+//! compared as text, so they should be written as `YYYY-MM-DD`. A summary
+//! without a `date` column uses the latest rate of all.
+//!
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+
+use super::amount::parse_decimal;
+use super::columns::{Columns, Layout};
+use super::error::{line_of, Error, Location};
+use super::input::Input;
+
+/// How many decimal places of a rate are kept.
+pub const RATE_SCALE: u32 = 12;
+/// How many minor units a converted amount may be off by and still match,
+/// since the two systems may round conversions differently.
+pub const FX_ROUNDING: i64 = 1;
+const RATE_COLUMNS: [&str; 3] = ["date", "pair", "rate"];
+
+/// A rate from the rates file, as used to convert a leg.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Rate {
+    /// The currencies the rate is quoted for, as `FROM/TO`
+    pub pair: String,
+    pub date: String,
+    /// The rate as written in the rates file
+    pub rate: String,
+    /// Whether the leg was converted from `TO` into `FROM`, by dividing by
+    /// the rate rather than multiplying
+    pub inverted: bool,
+    /// The rate in units of `10^-RATE_SCALE`
+    #[serde(skip)]
+    units: i64,
+}
+
+impl Rate {
+    /// Convert `amount`, in minor units at `from_scale` decimal places, into
+    /// minor units of the other currency at `to_scale` places, rounding
+    /// halves away from zero.
+    ///
+    /// Returns `None` if the result doesn't fit in an `i64`.
+    pub fn convert(&self, amount: i64, from_scale: u32, to_scale: u32) -> Option<i64> {
+        let ten = |power: u32| 10i128.checked_pow(power);
+        let amount = i128::from(amount);
+        let units = i128::from(self.units);
+        let (numerator, denominator) = match self.inverted {
+            false => (
+                amount.checked_mul(units)?.checked_mul(ten(to_scale)?)?,
+                ten(RATE_SCALE + from_scale)?,
+            ),
+            true => (
+                amount
+                    .checked_mul(ten(RATE_SCALE)?)?
+                    .checked_mul(ten(to_scale)?)?,
+                units.checked_mul(ten(from_scale)?)?,
+            ),
+        };
+        let mut quotient = numerator / denominator;
+        let remainder = numerator % denominator;
+        if remainder.abs() * 2 >= denominator {
+            quotient += numerator.signum();
+        }
+        if quotient < i128::from(i64::MIN) || quotient > i128::from(i64::MAX) {
+            return None;
+        }
+        Some(quotient as i64)
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.inverted {
+            false => write!(f, "{}@{}", self.pair, self.rate)?,
+            true => write!(f, "1/({}@{})", self.pair, self.rate)?,
+        }
+        write!(f, " on {}", self.date)
+    }
+}
+
+/// Every rate in a rates file, by currency pair.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Rates {
+    /// The rates for each `(FROM, TO)` pair, ordered by date
+    pairs: HashMap<(String, String), Vec<Rate>>,
+}
+
+impl Rates {
+    /// Load rates from a rates file, as described in the module docs.
+    ///
+    /// Pairs that aren't `FROM/TO` and rates that aren't positive decimals
+    /// are reported as [`Error::Parse`] errors pointing at the offending
+    /// row.
+    pub fn load<I: Into<Input>>(input: I) -> Result<Self, Error> {
+        let input = input.into();
+        let path = input.name().to_owned();
+        let mut reader = input.open()?;
+        let headers = reader.headers().map_err(|e| Error::csv(&path, e))?;
+        let layout = Columns::default()
+            .resolve(headers, &RATE_COLUMNS)
+            .map_err(|e| e.in_file(&path))?;
+        let mut rates = Rates::default();
+        for row in reader.records() {
+            let row = row.map_err(|e| Error::csv(&path, e))?;
+            rates
+                .insert_row(&row, &layout)
+                .map_err(|e| e.in_file(&path))?;
+        }
+        for by_date in rates.pairs.values_mut() {
+            by_date.sort_by(|a, b| a.date.cmp(&b.date));
+        }
+        Ok(rates)
+    }
+
+    fn insert_row(&mut self, row: &csv::StringRecord, layout: &Layout) -> Result<(), Error> {
+        let invalid = |column: &str, value: &str| Error::Parse {
+            location: Location {
+                file: Default::default(),
+                line: line_of(row),
+                column: column.to_owned(),
+            },
+            value: value.to_owned(),
+        };
+        let pair = layout.field(row, "pair")?;
+        let (from, to) = match pair.find('/') {
+            Some(slash) if slash > 0 && slash + 1 < pair.len() => {
+                (&pair[..slash], &pair[slash + 1..])
+            }
+            _ => return Err(invalid("pair", pair)),
+        };
+        let rate = layout.field(row, "rate")?;
+        let units = match parse_decimal(rate, RATE_SCALE) {
+            Some(units) if units > 0 => units,
+            _ => return Err(invalid("rate", rate)),
+        };
+        self.pairs
+            .entry((from.to_owned(), to.to_owned()))
+            .or_default()
+            .push(Rate {
+                pair: pair.to_owned(),
+                date: layout.date(row)?.to_owned(),
+                rate: rate.to_owned(),
+                inverted: false,
+                units,
+            });
+        Ok(())
+    }
+
+    /// The rate to convert `from` into `to` on `date`, or on the latest date
+    /// of all if `date` is empty.
+    pub fn find(&self, from: &str, to: &str, date: &str) -> Option<Rate> {
+        let on = |by_date: &Vec<Rate>| -> Option<Rate> {
+            let latest = match date {
+                "" => by_date.len(),
+                date => by_date.partition_point(|rate| rate.date.as_str() <= date),
+            };
+            latest.checked_sub(1).map(|latest| by_date[latest].clone())
+        };
+        let pair = |from: &str, to: &str| self.pairs.get(&(from.to_owned(), to.to_owned()));
+        if let Some(rate) = pair(from, to).and_then(on) {
+            return Some(rate);
+        }
+        pair(to, from).and_then(on).map(|rate| Rate {
+            inverted: true,
+            ..rate
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATES: &[u8] = b"date,pair,rate\n\
+                           2024-01-03,EUR/USD,1.0912\n\
+                           2024-01-02,EUR/USD,1.085\n\
+                           2024-01-02,USD/JPY,141.5\n";
+
+    #[test]
+    fn the_latest_rate_on_or_before_the_date_is_used() {
+        let rates = Rates::load(Input::reader("rates.csv", RATES)).unwrap();
+        let rate = |from, to, date| rates.find(from, to, date).map(|rate| rate.to_string());
+        assert_eq!(
+            rate("EUR", "USD", "2024-01-02"),
+            Some("EUR/USD@1.085 on 2024-01-02".into())
+        );
+        assert_eq!(
+            rate("EUR", "USD", "2024-01-05"),
+            Some("EUR/USD@1.0912 on 2024-01-03".into())
+        );
+        assert_eq!(
+            rate("EUR", "USD", ""),
+            Some("EUR/USD@1.0912 on 2024-01-03".into())
+        );
+        assert_eq!(
+            rate("JPY", "USD", "2024-01-02"),
+            Some("1/(USD/JPY@141.5) on 2024-01-02".into())
+        );
+        assert_eq!(rate("EUR", "USD", "2024-01-01"), None);
+        assert_eq!(rate("EUR", "JPY", "2024-01-02"), None);
+    }
+
+    #[test]
+    fn amounts_are_converted_between_scales() {
+        let rates = Rates::load(Input::reader("rates.csv", RATES)).unwrap();
+        let eur_usd = rates.find("EUR", "USD", "2024-01-02").unwrap();
+        // 100.00 EUR is 108.50 USD
+        assert_eq!(eur_usd.convert(10000, 2, 2), Some(10850));
+        assert_eq!(eur_usd.convert(-10000, 2, 2), Some(-10850));
+        // 0.01 EUR is 0.01085 USD, which rounds to 0.01
+        assert_eq!(eur_usd.convert(1, 2, 2), Some(1));
+        let jpy_usd = rates.find("JPY", "USD", "2024-01-02").unwrap();
+        // 14150 JPY is 100.00 USD
+        assert_eq!(jpy_usd.convert(14150, 0, 2), Some(10000));
+        assert_eq!(eur_usd.convert(i64::MAX, 0, 0), None);
+    }
+
+    #[test]
+    fn bad_rates_point_at_the_row() {
+        let err = Rates::load(Input::reader(
+            "rates.csv",
+            &b"date,pair,rate\n2024-01-02,EURUSD,1.085\n"[..],
+        ))
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "rates.csv:2: column `pair`: could not parse \"EURUSD\""
+        );
+        let err = Rates::load(Input::reader(
+            "rates.csv",
+            &b"date,pair,rate\n2024-01-02,EUR/USD,0\n"[..],
+        ))
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "rates.csv:2: column `rate`: could not parse \"0\""
+        );
+    }
+}
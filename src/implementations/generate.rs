@@ -0,0 +1,97 @@
+//! # Test Data
+//!
+//! Generates a summary and the ledger it should have produced, for
+//! benchmarks and for trying the implementations out at scale. Every
+//! account sends a number of transfers to new accounts, each with random
+//! ids and amounts, so the pair always reconciles.
+//!
+
+use rand::Rng;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use super::error::Error;
+
+/// Write `summary{N}.csv` and `txns{N}.csv` to `dir`, where `N` is the
+/// number of transfers, and return their paths.
+pub fn generate(
+    dir: &Path,
+    accounts: u32,
+    txns_per_account: u32,
+) -> Result<(PathBuf, PathBuf), Error> {
+    let records = u64::from(accounts) * u64::from(txns_per_account);
+    let summary_path = dir.join(format!("summary{}.csv", records));
+    let txns_path = dir.join(format!("txns{}.csv", records));
+    let mut summary_writer =
+        csv::Writer::from_path(&summary_path).map_err(|e| Error::csv(&summary_path, e))?;
+    let mut txn_writer =
+        csv::Writer::from_path(&txns_path).map_err(|e| Error::csv(&txns_path, e))?;
+    let summary_error = |e| Error::csv(&summary_path, e);
+    let txn_error = |e| Error::csv(&txns_path, e);
+
+    let mut rng = rand::thread_rng();
+    txn_writer
+        .write_record(["account", "txn_id", "amount", "balance"])
+        .map_err(txn_error)?;
+    summary_writer
+        .write_record(["id", "from", "to", "amount"])
+        .map_err(summary_error)?;
+    for _ in 0..accounts {
+        let from_account = Uuid::new_v4().to_string();
+        let mut from_balance: i64 = rng.gen_range(0, 100_000);
+        for _ in 0..txns_per_account {
+            let txn_id = Uuid::new_v4().to_string();
+            let amount: u16 = rng.gen_range(0, 10_000);
+            let amount_str = amount.to_string();
+            let to_account = Uuid::new_v4().to_string();
+            let to_start_balance: i64 = rng.gen_range(0, 100_000);
+            from_balance -= i64::from(amount);
+            summary_writer
+                .write_record([&txn_id, &from_account, &to_account, &amount_str])
+                .map_err(summary_error)?;
+            txn_writer
+                .write_record([
+                    &from_account,
+                    &txn_id,
+                    &format!("-{}", amount_str),
+                    &from_balance.to_string(),
+                ])
+                .map_err(txn_error)?;
+            txn_writer
+                .write_record([
+                    &to_account,
+                    &txn_id,
+                    &amount_str,
+                    &(to_start_balance + i64::from(amount)).to_string(),
+                ])
+                .map_err(txn_error)?;
+        }
+    }
+    summary_writer
+        .flush()
+        .map_err(|e| summary_error(e.into()))?;
+    txn_writer.flush().map_err(|e| txn_error(e.into()))?;
+    Ok((summary_path, txns_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::implementations::options::Options;
+    use crate::implementations::owned;
+
+    #[test]
+    fn generated_inputs_reconcile() {
+        let dir = tempfile::tempdir().unwrap();
+        let (summary, txns) = generate(dir.path(), 3, 4).unwrap();
+        assert_eq!(summary, dir.path().join("summary12.csv"));
+        let options = Options {
+            check_balances: true,
+            ..Default::default()
+        };
+        let report = owned::validate_all(summary.as_path(), txns.as_path(), &options).unwrap();
+        assert_eq!(report.summaries, 12);
+        assert_eq!(report.txns, 24);
+        assert!(report.is_valid(), "{}", report);
+    }
+}
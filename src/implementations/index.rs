@@ -0,0 +1,494 @@
+//! # Ledger Index
+//!
+//! The ledger only ever grows by rows appended to its end, yet every run
+//! would otherwise parse all of it again. An index kept on disk between
+//! runs records where each account and txn_id's row starts, so a run only
+//! has to parse what was appended since the last one, and reads older rows
+//! at their offsets when a summary leg refers to them.
+//!
+//! The index also records how many bytes of the ledger it covers and a
+//! CRC-32 checksum of the last [`CHECKED_TAIL`] of them. If those bytes
+//! have changed, because the ledger was rewritten rather than appended to,
+//! the index is thrown away and rebuilt from the whole ledger, as it is
+//! when the index file is missing or can't be read. A rewrite that leaves
+//! them as they were isn't noticed; `index --rebuild` starts over by hand.
+//!
+//! Each account's latest balance is kept as well, so that the first new
+//! posting in an account can be checked against the last one indexed.
+//!
+//! The index file is a log of JSON lines. Each run appends the rows and
+//! balances it indexed, followed by a line recording how much of the
+//! ledger they cover. Lines after the last such record, left by a run that
+//! didn't finish, are ignored, and the file is written afresh on the next
+//! save. A run then costs the rows appended to the ledger, plus reading
+//! the whole index file back into memory: that grows with the number of
+//! keys in the ledger, but involves no CSV parsing and no reading of the
+//! ledger's older rows.
+//!
+
+use crc32fast::Hasher;
+use csv::{Position, Reader, ReaderBuilder, StringRecord};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use super::error::Error;
+
+/// Bumped whenever the layout of the index file changes, so that an index
+/// written by another version is rebuilt rather than misread.
+const INDEX_VERSION: u32 = 2;
+
+/// How many bytes at the end of the indexed part of the ledger are
+/// checksummed: 64 KiB.
+pub const CHECKED_TAIL: u64 = 64 << 10;
+
+/// Reads the rows appended to a ledger, from [`LedgerIndex::read_appended`].
+pub type Appended = Reader<Cursor<Vec<u8>>>;
+
+/// Where a ledger row starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Byte offset of the row from the start of the ledger
+    pub offset: u64,
+    pub line: u64,
+}
+
+/// An index of the ledger, as described in the module docs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LedgerIndex {
+    /// How many bytes from the start of the ledger are indexed
+    len: u64,
+    /// CRC-32 of the last [`CHECKED_TAIL`] indexed bytes
+    checksum: u32,
+    /// The line the first unindexed row starts on
+    next_line: u64,
+    /// The first row for each account and txn_id
+    rows: HashMap<(String, String), Entry>,
+    /// The balance after each account's latest posting
+    balances: HashMap<String, i64>,
+    /// Keys indexed since the index was loaded
+    added: Vec<(String, String)>,
+    /// Accounts posted to since the index was loaded
+    posted: HashSet<String>,
+    /// Whether the index file has to be written afresh rather than
+    /// appended to
+    rewrite: bool,
+}
+
+impl Default for LedgerIndex {
+    fn default() -> Self {
+        LedgerIndex {
+            len: 0,
+            checksum: 0,
+            next_line: 1,
+            rows: HashMap::new(),
+            balances: HashMap::new(),
+            added: Vec::new(),
+            posted: HashSet::new(),
+            rewrite: true,
+        }
+    }
+}
+
+/// One line of the index file.
+#[derive(Serialize, Deserialize)]
+enum Record {
+    /// The first line, giving the layout of the rest
+    Version(u32),
+    Row(String, String, Entry),
+    Balance(String, i64),
+    /// The lines before this one cover this much of the ledger
+    Covers {
+        len: u64,
+        checksum: u32,
+        next_line: u64,
+    },
+}
+
+impl LedgerIndex {
+    /// Load the index at `path` if it still describes the start of
+    /// `ledger`, or start an empty one otherwise.
+    pub fn load(path: &Path, ledger: &Path) -> Result<Self, Error> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(LedgerIndex::default()),
+            Err(e) => return Err(Error::csv(path, e.into())),
+        };
+        let mut lines = BufReader::new(file).lines();
+        let record = |line: io::Result<String>| {
+            line.ok()
+                .and_then(|line| serde_json::from_str::<Record>(&line).ok())
+        };
+        match lines.next().map(record) {
+            Some(Some(Record::Version(INDEX_VERSION))) => {}
+            _ => return Ok(LedgerIndex::default()),
+        }
+
+        let mut index = LedgerIndex {
+            rewrite: false,
+            ..Default::default()
+        };
+        let mut pending = Vec::new();
+        for line in lines {
+            match record(line) {
+                Some(Record::Covers {
+                    len,
+                    checksum,
+                    next_line,
+                }) => {
+                    for record in pending.drain(..) {
+                        match record {
+                            Record::Row(account, txn_id, entry) => {
+                                index.rows.insert((account, txn_id), entry);
+                            }
+                            Record::Balance(account, balance) => {
+                                index.balances.insert(account, balance);
+                            }
+                            _ => {}
+                        }
+                    }
+                    index.len = len;
+                    index.checksum = checksum;
+                    index.next_line = next_line;
+                }
+                Some(record) => pending.push(record),
+                // Whatever follows the last complete run, including
+                // anything that can't be read, is dropped when the file is
+                // next written
+                None => {
+                    index.rewrite = true;
+                    break;
+                }
+            }
+        }
+        index.rewrite |= !pending.is_empty();
+
+        let mut file = File::open(ledger).map_err(|e| Error::csv(ledger, e.into()))?;
+        match tail_checksum(&mut file, index.len).map_err(|e| Error::csv(ledger, e.into()))? {
+            Some(checksum) if checksum == index.checksum => Ok(index),
+            _ => Ok(LedgerIndex::default()),
+        }
+    }
+
+    /// Save what's been indexed since the index was loaded to `path`,
+    /// appending to the file when it can. A file written afresh replaces
+    /// whatever was there only once it is complete.
+    pub fn save(&mut self, path: &Path) -> Result<(), Error> {
+        let io_error = |e: io::Error| Error::csv(path, e.into());
+        let covers = Record::Covers {
+            len: self.len,
+            checksum: self.checksum,
+            next_line: self.next_line,
+        };
+        if self.rewrite {
+            let dir = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            let mut file = tempfile::NamedTempFile::new_in(dir).map_err(io_error)?;
+            {
+                let mut writer = BufWriter::new(&mut file);
+                write_record(&mut writer, &Record::Version(INDEX_VERSION)).map_err(io_error)?;
+                for ((account, txn_id), entry) in self.rows.iter() {
+                    let row = Record::Row(account.clone(), txn_id.clone(), *entry);
+                    write_record(&mut writer, &row).map_err(io_error)?;
+                }
+                for (account, balance) in self.balances.iter() {
+                    let balance = Record::Balance(account.clone(), *balance);
+                    write_record(&mut writer, &balance).map_err(io_error)?;
+                }
+                write_record(&mut writer, &covers).map_err(io_error)?;
+                writer.flush().map_err(io_error)?;
+            }
+            file.persist(path).map_err(|e| io_error(e.error))?;
+        } else {
+            let file = OpenOptions::new()
+                .append(true)
+                .open(path)
+                .map_err(io_error)?;
+            let mut writer = BufWriter::new(file);
+            for key in self.added.iter() {
+                let row = Record::Row(key.0.clone(), key.1.clone(), self.rows[key]);
+                write_record(&mut writer, &row).map_err(io_error)?;
+            }
+            for account in self.posted.iter() {
+                let balance = Record::Balance(account.clone(), self.balances[account]);
+                write_record(&mut writer, &balance).map_err(io_error)?;
+            }
+            write_record(&mut writer, &covers).map_err(io_error)?;
+            writer.flush().map_err(io_error)?;
+        }
+        self.added.clear();
+        self.posted.clear();
+        self.rewrite = false;
+        Ok(())
+    }
+
+    /// Read the rows appended to `ledger`, whose fields are separated by
+    /// `delimiter`, since it was last indexed, and count them as indexed
+    /// from now on.
+    ///
+    /// A last row without its line terminator may still be being written,
+    /// so it's left for the next run, along with anything after it.
+    ///
+    /// Returns the ledger's header row, and a reader over the new rows
+    /// whose positions are those of the rows in the whole ledger.
+    pub fn read_appended(
+        &mut self,
+        ledger: &Path,
+        delimiter: u8,
+    ) -> Result<(StringRecord, Appended), Error> {
+        let csv_error = |e: csv::Error| Error::csv(ledger, e);
+        let io_error = |e: io::Error| Error::csv(ledger, e.into());
+        let mut file = File::open(ledger).map_err(io_error)?;
+        let headers = ReaderBuilder::new()
+            .delimiter(delimiter)
+            .from_reader(&mut file)
+            .headers()
+            .map_err(csv_error)?
+            .clone();
+        let mut appended = Vec::new();
+        file.seek(SeekFrom::Start(self.len)).map_err(io_error)?;
+        file.read_to_end(&mut appended).map_err(io_error)?;
+        appended.truncate(complete_len(&appended));
+
+        let mut position = Position::new();
+        position.set_byte(self.len).set_line(self.next_line);
+        self.next_line += appended.iter().filter(|&&byte| byte == b'\n').count() as u64;
+        self.len += appended.len() as u64;
+        self.checksum = tail_checksum(&mut file, self.len)
+            .map_err(io_error)?
+            .unwrap_or_default();
+
+        let fresh = position.byte() == 0;
+        let mut reader = ReaderBuilder::new()
+            .delimiter(delimiter)
+            .has_headers(fresh)
+            .from_reader(Cursor::new(appended));
+        if !fresh {
+            reader
+                .seek_raw(SeekFrom::Start(0), position)
+                .map_err(csv_error)?;
+        }
+        Ok((headers, reader))
+    }
+
+    /// Where the row for `key` starts.
+    pub fn get(&self, key: &(String, String)) -> Option<Entry> {
+        self.rows.get(key).copied()
+    }
+
+    /// Record where the row for `key` starts, unless a row with the same
+    /// key was indexed first, in which case that one's entry is returned.
+    pub fn insert(&mut self, key: (String, String), entry: Entry) -> Option<Entry> {
+        match self.rows.get(&key) {
+            Some(first) => Some(*first),
+            None => {
+                self.rows.insert(key.clone(), entry);
+                self.added.push(key);
+                None
+            }
+        }
+    }
+
+    /// How many keys are indexed.
+    pub fn rows(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// How many bytes from the start of the ledger are indexed.
+    pub fn indexed_bytes(&self) -> u64 {
+        self.len
+    }
+
+    /// Record the balance after an account's latest posting, returning the
+    /// one before it.
+    pub fn post(&mut self, account: &str, balance: i64) -> Option<i64> {
+        if !self.posted.contains(account) {
+            self.posted.insert(account.to_owned());
+        }
+        self.balances.insert(account.to_owned(), balance)
+    }
+}
+
+/// Reads individual rows of a ledger at the offsets an index gives.
+pub struct IndexedRows {
+    reader: Reader<File>,
+    record: StringRecord,
+}
+
+impl IndexedRows {
+    pub fn open(ledger: &Path, delimiter: u8) -> Result<Self, Error> {
+        let file = File::open(ledger).map_err(|e| Error::csv(ledger, e.into()))?;
+        Ok(IndexedRows {
+            reader: ReaderBuilder::new()
+                .delimiter(delimiter)
+                .has_headers(false)
+                .from_reader(file),
+            record: StringRecord::new(),
+        })
+    }
+
+    /// The row that starts at `entry`.
+    pub fn read(&mut self, ledger: &Path, entry: Entry) -> Result<&StringRecord, Error> {
+        let mut position = Position::new();
+        position.set_byte(entry.offset).set_line(entry.line);
+        let found = self
+            .reader
+            .seek(position)
+            .and_then(|_| self.reader.read_record(&mut self.record))
+            .map_err(|e| Error::csv(ledger, e))?;
+        if !found {
+            let message = format!("no row at byte {} of the index", entry.offset);
+            return Err(Error::csv(
+                ledger,
+                io::Error::new(io::ErrorKind::UnexpectedEof, message).into(),
+            ));
+        }
+        Ok(&self.record)
+    }
+}
+
+/// CRC-32 of the last [`CHECKED_TAIL`] bytes before `len` in `file`, or
+/// `None` if the file is shorter than `len`.
+fn tail_checksum(file: &mut File, len: u64) -> io::Result<Option<u32>> {
+    let start = len.saturating_sub(CHECKED_TAIL);
+    file.seek(SeekFrom::Start(start))?;
+    let mut hasher = Hasher::new();
+    let read = io::copy(&mut file.take(len - start), &mut HashWriter(&mut hasher))?;
+    Ok(if read == len - start {
+        Some(hasher.finalize())
+    } else {
+        None
+    })
+}
+
+fn write_record<W: Write>(writer: &mut W, record: &Record) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, record)?;
+    writer.write_all(b"\n")
+}
+
+/// How many bytes from the start of `bytes` hold whole records, up to and
+/// including the last line terminator outside a quoted field. `bytes` must
+/// start at the beginning of a record.
+fn complete_len(bytes: &[u8]) -> usize {
+    let mut quoted = false;
+    let mut len = 0;
+    for (i, &byte) in bytes.iter().enumerate() {
+        match byte {
+            // An escaped quote toggles twice, leaving the field quoted
+            b'"' => quoted = !quoted,
+            b'\n' if !quoted => len = i + 1,
+            _ => {}
+        }
+    }
+    len
+}
+
+/// Feeds whatever is written to it into a checksum.
+struct HashWriter<'a>(&'a mut Hasher);
+
+impl io::Write for HashWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+
+    #[test]
+    fn a_row_still_being_written_is_left_for_the_next_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let ledger = dir.path().join("txns.csv");
+        fs::write(&ledger, "account,txn_id,amount,balance\na,1,-5,95\nb,1,5").unwrap();
+        let append = |bytes: &[u8]| {
+            let mut file = OpenOptions::new().append(true).open(&ledger).unwrap();
+            file.write_all(bytes).unwrap();
+        };
+        let read = |index: &mut LedgerIndex| {
+            let (_, mut reader) = index.read_appended(&ledger, b',').unwrap();
+            reader
+                .records()
+                .map(|row| {
+                    let row = row.unwrap();
+                    let line = row.position().unwrap().line();
+                    (line, row.iter().collect::<Vec<_>>().join(","))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut index = LedgerIndex::default();
+        assert_eq!(read(&mut index), vec![(2, "a,1,-5,95".to_owned())]);
+        append(b",105\nc,\"2\n");
+        assert_eq!(read(&mut index), vec![(3, "b,1,5,105".to_owned())]);
+        append(b"\"\"x\",1,1\n");
+        assert_eq!(read(&mut index), vec![(4, "c,2\n\"x,1,1".to_owned())]);
+        assert_eq!(index.indexed_bytes(), fs::metadata(&ledger).unwrap().len());
+    }
+
+    #[test]
+    fn saves_append_to_the_index_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let (ledger, path) = (dir.path().join("txns.csv"), dir.path().join("txns.index"));
+        let index_rows = |rows: &[(&str, u64)]| {
+            let mut index = LedgerIndex::load(&path, &ledger).unwrap();
+            let (_, mut reader) = index.read_appended(&ledger, b',').unwrap();
+            for ((account, line), row) in rows.iter().zip(reader.records()) {
+                let position = row.unwrap().position().unwrap().clone();
+                let key = (account.to_string(), "1".to_owned());
+                let entry = Entry {
+                    offset: position.byte(),
+                    line: *line,
+                };
+                assert_eq!(index.insert(key, entry), None);
+                index.post(account, 0);
+            }
+            index.save(&path).unwrap();
+            index
+        };
+        fs::write(&ledger, "account,txn_id,amount,balance\na,1,0,0\n").unwrap();
+        let first = index_rows(&[("a", 2)]);
+        let saved = fs::read(&path).unwrap();
+
+        OpenOptions::new()
+            .append(true)
+            .open(&ledger)
+            .unwrap()
+            .write_all(b"b,1,0,0\n")
+            .unwrap();
+        let second = index_rows(&[("b", 3)]);
+        assert!(fs::read(&path).unwrap().starts_with(&saved));
+        assert_eq!(LedgerIndex::load(&path, &ledger).unwrap(), second);
+        assert_ne!(first, second);
+
+        // A run that stopped partway through saving is ignored, and the
+        // file written afresh the next time
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"Row\":[\"c\",")
+            .unwrap();
+        let mut loaded = LedgerIndex::load(&path, &ledger).unwrap();
+        assert_eq!(loaded.rows(), 2);
+        assert!(loaded.rewrite);
+        loaded.save(&path).unwrap();
+        assert_eq!(LedgerIndex::load(&path, &ledger).unwrap(), loaded);
+
+        // So is one for a ledger whose indexed bytes have changed
+        fs::write(&ledger, "account,txn_id,amount,balance\nz,1,0,0\nb,1,0,0\n").unwrap();
+        assert_eq!(LedgerIndex::load(&path, &ledger).unwrap().rows(), 0);
+    }
+}
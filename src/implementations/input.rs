@@ -0,0 +1,322 @@
+//! # Inputs
+//!
+//! Both CSVs can be read from a path on disk, from stdin, or from any other
+//! reader, such as an in-memory buffer or a socket. Either way an input has
+//! a name, which is what errors and reports use to say where a problem was
+//! found.
+//!
+//! Gzip and zstd compressed inputs are decompressed on the fly. They are
+//! recognised by a `.gz` or `.zst` extension, or failing that by their
+//! leading magic bytes, so piped data needs no hint.
+//!
+
+use csv::{Reader, ReaderBuilder};
+use flate2::read::MultiGzDecoder;
+use memmap2::Mmap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Cursor, Read};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+use super::error::Error;
+
+pub struct Input {
+    name: PathBuf,
+    reader: Option<Box<dyn Read>>,
+}
+
+impl Input {
+    /// A CSV file on disk. It isn't opened until it is read.
+    ///
+    /// A path of `-` means [`Input::stdin`].
+    pub fn path<P: AsRef<Path>>(path: P) -> Self {
+        if path.as_ref() == Path::new("-") {
+            return Input::stdin();
+        }
+        Input {
+            name: path.as_ref().to_owned(),
+            reader: None,
+        }
+    }
+
+    /// CSV data from any reader, reported under `name`.
+    pub fn reader<N: Into<PathBuf>, R: Read + 'static>(name: N, reader: R) -> Self {
+        Input {
+            name: name.into(),
+            reader: Some(Box::new(reader)),
+        }
+    }
+
+    /// CSV data piped in on stdin.
+    pub fn stdin() -> Self {
+        Input::reader("<stdin>", io::stdin())
+    }
+
+    pub fn name(&self) -> &Path {
+        &self.name
+    }
+
+    /// Whether this input reads from a stream that can only be read once.
+    pub fn is_stream(&self) -> bool {
+        self.reader.is_some()
+    }
+
+    /// The path of an uncompressed file on disk, which unlike a stream or a
+    /// compressed file can be read from any offset.
+    pub fn seekable_path(&self) -> Result<&Path, Error> {
+        let unseekable = || {
+            let message = "expected an uncompressed file on disk";
+            Error::csv(
+                &self.name,
+                io::Error::new(io::ErrorKind::InvalidInput, message).into(),
+            )
+        };
+        if self.is_stream() || Compression::from_extension(&self.name).is_some() {
+            return Err(unseekable());
+        }
+        let mut magic = Vec::with_capacity(ZSTD_MAGIC.len());
+        File::open(&self.name)
+            .and_then(|file| file.take(ZSTD_MAGIC.len() as u64).read_to_end(&mut magic))
+            .map_err(|e| Error::csv(&self.name, e.into()))?;
+        match Compression::from_magic(&magic) {
+            Compression::None => Ok(&self.name),
+            _ => Err(unseekable()),
+        }
+    }
+
+    /// Open the input as a CSV reader that expects a header row,
+    /// decompressing it if need be.
+    pub fn open(self) -> Result<Reader<Box<dyn Read>>, Error> {
+        self.open_delimited(b',')
+    }
+
+    /// Like [`Input::open`], for fields separated by `delimiter`.
+    pub fn open_delimited(self, delimiter: u8) -> Result<Reader<Box<dyn Read>>, Error> {
+        let name = self.name;
+        let reader = match self.reader {
+            Some(reader) => reader,
+            None => Box::new(File::open(&name).map_err(|e| Error::csv(&name, e.into()))?),
+        };
+        let reader = decompress(&name, reader).map_err(|e| Error::csv(&name, e.into()))?;
+        Ok(ReaderBuilder::new()
+            .delimiter(delimiter)
+            .from_reader(reader))
+    }
+
+    /// The whole input as bytes. An uncompressed file on disk is mapped into
+    /// memory rather than read; anything else is decompressed and read in.
+    ///
+    /// The file must not be truncated while the map is alive, which would
+    /// make reading it fail with `SIGBUS`.
+    pub fn bytes(self) -> Result<Bytes, Error> {
+        let name = self.name;
+        if let Some(reader) = self.reader {
+            return read_all(&name, reader);
+        }
+        let file = File::open(&name).map_err(|e| Error::csv(&name, e.into()))?;
+        let len = file
+            .metadata()
+            .map_err(|e| Error::csv(&name, e.into()))?
+            .len();
+        if len == 0 {
+            return Ok(Bytes::Read(Vec::new()));
+        }
+        // SAFETY: the map is only ever read, and the caller is warned above
+        // about truncating the file underneath it
+        let map = unsafe { Mmap::map(&file) }.map_err(|e| Error::csv(&name, e.into()))?;
+        let compression =
+            Compression::from_extension(&name).unwrap_or_else(|| Compression::from_magic(&map));
+        match compression {
+            Compression::None => Ok(Bytes::Mapped(map)),
+            _ => read_all(&name, Box::new(file)),
+        }
+    }
+}
+
+/// The contents of an [`Input`], from [`Input::bytes`].
+pub enum Bytes {
+    /// An uncompressed file, mapped into memory in place
+    Mapped(Mmap),
+    /// A stream or a compressed file, read into memory
+    Read(Vec<u8>),
+}
+
+impl Deref for Bytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Bytes::Mapped(map) => map,
+            Bytes::Read(bytes) => bytes,
+        }
+    }
+}
+
+impl fmt::Debug for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self {
+            Bytes::Mapped(_) => "Mapped",
+            Bytes::Read(_) => "Read",
+        };
+        f.debug_tuple(kind).field(&self.len()).finish()
+    }
+}
+
+fn read_all(name: &Path, reader: Box<dyn Read>) -> Result<Bytes, Error> {
+    let mut bytes = Vec::new();
+    decompress(name, reader)
+        .and_then(|mut reader| reader.read_to_end(&mut bytes))
+        .map_err(|e| Error::csv(name, e.into()))?;
+    Ok(Bytes::Read(bytes))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+impl Compression {
+    fn from_extension(name: &Path) -> Option<Self> {
+        match name.extension()?.to_str()? {
+            "gz" => Some(Compression::Gzip),
+            "zst" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    fn from_magic(magic: &[u8]) -> Self {
+        if magic.starts_with(&GZIP_MAGIC) {
+            Compression::Gzip
+        } else if magic.starts_with(&ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// Wrap `reader` in a decoder for whatever compression it uses, if any.
+///
+/// The magic bytes are read up front and put back in front of the rest of
+/// the stream, which works for pipes as well as files.
+fn decompress(name: &Path, mut reader: Box<dyn Read>) -> io::Result<Box<dyn Read>> {
+    let mut magic = Vec::with_capacity(ZSTD_MAGIC.len());
+    reader
+        .by_ref()
+        .take(ZSTD_MAGIC.len() as u64)
+        .read_to_end(&mut magic)?;
+    let compression =
+        Compression::from_extension(name).unwrap_or_else(|| Compression::from_magic(&magic));
+    let reader = Cursor::new(magic).chain(reader);
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+        Compression::Zstd => Box::new(zstd::Decoder::new(reader)?),
+    })
+}
+
+impl fmt::Debug for Input {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Input").field("name", &self.name).finish()
+    }
+}
+
+impl From<&str> for Input {
+    fn from(path: &str) -> Self {
+        Input::path(path)
+    }
+}
+
+impl From<&String> for Input {
+    fn from(path: &String) -> Self {
+        Input::path(path)
+    }
+}
+
+impl From<String> for Input {
+    fn from(path: String) -> Self {
+        Input::path(path)
+    }
+}
+
+impl From<&Path> for Input {
+    fn from(path: &Path) -> Self {
+        Input::path(path)
+    }
+}
+
+impl From<PathBuf> for Input {
+    fn from(path: PathBuf) -> Self {
+        Input::path(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression as Level;
+    use std::io::Write;
+
+    const CSV: &[u8] = b"account,txn_id,amount,balance\na,1,-100,1000\n";
+
+    fn read_all(input: Input) -> Vec<csv::StringRecord> {
+        input
+            .open()
+            .unwrap()
+            .records()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn compressed_readers_are_detected_by_magic_bytes() {
+        let mut gzip = GzEncoder::new(Vec::new(), Level::default());
+        gzip.write_all(CSV).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let zstd = zstd::encode_all(CSV, 0).unwrap();
+
+        let plain = read_all(Input::reader("plain", CSV));
+        assert_eq!(plain.len(), 1);
+        assert_eq!(read_all(Input::reader("piped", Cursor::new(gzip))), plain);
+        assert_eq!(read_all(Input::reader("piped", Cursor::new(zstd))), plain);
+    }
+
+    #[test]
+    fn plain_files_are_mapped_and_others_read() {
+        let plain = Input::path("resources/txns0.csv").bytes().unwrap();
+        let gzip = Input::path("resources/txns0.csv.gz").bytes().unwrap();
+        let piped = Input::reader("piped", CSV).bytes().unwrap();
+        assert!(matches!(plain, Bytes::Mapped(_)));
+        assert!(matches!(gzip, Bytes::Read(_)));
+        assert_eq!(*plain, *gzip);
+        assert_eq!(&*piped, CSV);
+    }
+
+    #[test]
+    fn compressed_files_are_read_transparently() {
+        assert_eq!(
+            read_all(Input::path("resources/txns0.csv.gz")),
+            read_all(Input::path("resources/txns0.csv"))
+        );
+    }
+
+    #[test]
+    fn short_inputs_are_not_mistaken_for_compressed_ones() {
+        assert!(read_all(Input::reader("empty", &b""[..])).is_empty());
+        assert!(read_all(Input::reader("tiny", &b"a\n"[..])).is_empty());
+    }
+
+    #[test]
+    fn dash_means_stdin() {
+        let input = Input::path("-");
+        assert_eq!(input.name(), Path::new("<stdin>"));
+        assert!(input.is_stream());
+    }
+}
@@ -0,0 +1,298 @@
+//! # Memory-Mapped Implementation
+//!
+//! Most of the time spent loading a large ledger goes to copying each of
+//! its fields into a `String`. This implementation maps the transactions
+//! file into memory instead, and its rows, and the index built over them,
+//! borrow their text straight from the map. The file is never copied onto
+//! the heap; only the odd field that had quotes to unescape is.
+//!
+//! Once the ledger is indexed, the summary is checked exactly as in the
+//! references implementation. Ledgers that can't be mapped, such as
+//! streams and compressed files, are read into memory first.
+//!
+
+use csv::{ReaderBuilder, StringRecord};
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::str;
+
+use super::amount::Scales;
+use super::columns::{Columns, Layout, TXN_COLUMNS};
+use super::error::{line_of, Error};
+use super::input::Input;
+use super::options::Options;
+use super::refs::{self, Transaction};
+use super::report::Report;
+use super::validator::Validator;
+
+/// Where a field's text sits.
+#[derive(Debug, Clone)]
+enum Span {
+    /// In the mapped file itself
+    Mapped(Range<usize>),
+    /// In the buffer of fields that differ from their raw text
+    Unescaped(Range<usize>),
+}
+
+/// A ledger row, as spans of the mapped file.
+#[derive(Debug)]
+struct Row {
+    account: Span,
+    txn_id: Span,
+    amount: i64,
+    balance: i64,
+    currency: Span,
+    date: Span,
+    line: u64,
+}
+
+/// The ledger's rows, pointing into the text of the file they came from.
+#[derive(Debug, Default)]
+struct Ledger {
+    rows: Vec<Row>,
+    /// Fields whose value isn't their raw text, because they were quoted
+    /// and contained quotes
+    unescaped: String,
+}
+
+impl Ledger {
+    /// Every row, with its fields borrowed from `text`, which must be the
+    /// text the rows were read from.
+    fn transactions<'a>(&'a self, text: &'a str) -> impl Iterator<Item = Transaction<'a>> {
+        self.rows.iter().map(move |row| Transaction {
+            account: self.resolve(text, &row.account),
+            txn_id: self.resolve(text, &row.txn_id),
+            amount: row.amount,
+            balance: row.balance,
+            currency: self.resolve(text, &row.currency),
+            date: self.resolve(text, &row.date),
+            line: row.line,
+        })
+    }
+
+    fn resolve<'a>(&'a self, text: &'a str, span: &Span) -> &'a str {
+        match span {
+            Span::Mapped(range) => &text[range.clone()],
+            Span::Unescaped(range) => &self.unescaped[range.clone()],
+        }
+    }
+
+    /// Find column `index` of `row`, which starts at byte `start` of `text`.
+    ///
+    /// Unless something before it was quoted, a field starts one delimiter
+    /// further on per field before it. Otherwise, any match for its value
+    /// in the rest of the row will do, so long as it has no quotes of its
+    /// own to unescape.
+    fn span(&mut self, text: &str, row: &StringRecord, index: usize, start: usize) -> Span {
+        let value = row.get(index).unwrap_or_default();
+        let guess = start + row.range(index).map_or(0, |range| range.start) + index;
+        if text.get(guess..guess + value.len()) == Some(value) {
+            return Span::Mapped(guess..guess + value.len());
+        }
+        let rest = &text[start..];
+        let end = rest.find('\n').map_or(rest.len(), |end| end + 1);
+        match rest[..end].find(value) {
+            Some(at) if !value.is_empty() => Span::Mapped(start + at..start + at + value.len()),
+            _ => {
+                let from = self.unescaped.len();
+                self.unescaped.push_str(value);
+                Span::Unescaped(from..self.unescaped.len())
+            }
+        }
+    }
+}
+
+/// Check that the whole file is UTF-8, so that its rows can borrow `&str`s
+/// from it.
+fn utf8<'a>(path: &Path, bytes: &'a [u8]) -> Result<&'a str, Error> {
+    str::from_utf8(bytes).map_err(|e| {
+        let line = bytes[..e.valid_up_to()]
+            .iter()
+            .filter(|&&byte| byte == b'\n')
+            .count()
+            + 1;
+        let message = format!("invalid UTF-8 on line {}", line);
+        Error::csv(
+            path,
+            io::Error::new(io::ErrorKind::InvalidData, message).into(),
+        )
+    })
+}
+
+/// Parse the rows of the ledger in `text`.
+fn parse(text: &str, path: &Path, columns: &Columns, scales: &Scales) -> Result<Ledger, Error> {
+    let mut reader = ReaderBuilder::new()
+        .delimiter(columns.delimiter())
+        .from_reader(text.as_bytes());
+    let headers = reader.headers().map_err(|e| Error::csv(path, e))?;
+    let layout = columns
+        .resolve(headers, &TXN_COLUMNS)
+        .map_err(|e| e.in_file(path))?
+        .with_scales(scales);
+    let mut ledger = Ledger::default();
+    let mut row = StringRecord::new();
+    while reader
+        .read_record(&mut row)
+        .map_err(|e| Error::csv(path, e))?
+    {
+        let parsed = parse_row(&mut ledger, text, &row, &layout).map_err(|e| e.in_file(path))?;
+        ledger.rows.push(parsed);
+    }
+    Ok(ledger)
+}
+
+fn parse_row(
+    ledger: &mut Ledger,
+    text: &str,
+    row: &StringRecord,
+    layout: &Layout,
+) -> Result<Row, Error> {
+    // Look the fields up first, so that a short row is reported as such
+    layout.field(row, "account")?;
+    layout.field(row, "txn_id")?;
+    let currency = layout.optional(row, "currency")?;
+    let date = layout.date(row)?;
+    let start = row.position().map_or(0, |pos| pos.byte() as usize);
+    Ok(Row {
+        account: ledger.span(text, row, layout.index("account"), start),
+        txn_id: ledger.span(text, row, layout.index("txn_id"), start),
+        amount: layout.amount(row, "amount")?,
+        balance: layout.amount(row, "balance")?,
+        currency: match currency {
+            "" => Span::Mapped(0..0),
+            _ => ledger.span(text, row, layout.index("currency"), start),
+        },
+        // The date is the start of its field, which may go on with a time
+        date: match date {
+            "" => Span::Mapped(0..0),
+            _ => match ledger.span(text, row, layout.index("date"), start) {
+                Span::Mapped(range) => Span::Mapped(range.start..range.start + date.len()),
+                Span::Unescaped(range) => Span::Unescaped(range.start..range.start + date.len()),
+            },
+        },
+        line: line_of(row),
+    })
+}
+
+pub fn validate<S: Into<Input>, T: Into<Input>>(
+    summary: S,
+    ledger: T,
+    options: &Options,
+) -> Result<&'static str, Error> {
+    let summary = summary.into();
+    let summary_path = summary.name().to_owned();
+    let (rows, layout) =
+        refs::load_summary_rows(summary, &options.columns.summary, &options.scales)?;
+    let summaries = refs::parse_summaries(&rows, &layout, &summary_path)?;
+    let ledger = ledger.into();
+    let path = ledger.name().to_owned();
+    let bytes = ledger.bytes()?;
+    let text = utf8(&path, &bytes)?;
+    let ledger = parse(text, &path, &options.columns.txns, &options.scales)?;
+    let (txns, txn_duplicates) = refs::index(ledger.transactions(text));
+    refs::check(&summaries, &txns, txn_duplicates, options)
+}
+
+/// Walk every summary row and collect all findings, rather than stopping
+/// at the first one.
+pub fn validate_all<S: Into<Input>, T: Into<Input>>(
+    summary: S,
+    ledger: T,
+    options: &Options,
+) -> Result<Report, Error> {
+    let summary = summary.into();
+    let summary_path = summary.name().to_owned();
+    let (rows, layout) =
+        refs::load_summary_rows(summary, &options.columns.summary, &options.scales)?;
+    let summaries = refs::parse_summaries(&rows, &layout, &summary_path)?;
+    let ledger = ledger.into();
+    let path = ledger.name().to_owned();
+    let bytes = ledger.bytes()?;
+    let text = utf8(&path, &bytes)?;
+    let ledger = parse(text, &path, &options.columns.txns, &options.scales)?;
+    let (txns, txn_duplicates) = refs::index(ledger.transactions(text));
+    refs::check_all(&summaries, &txns, txn_duplicates, options)
+}
+
+/// The memory-mapped implementation as a [`Validator`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Mmap;
+
+impl Validator for Mmap {
+    fn name(&self) -> &'static str {
+        "mmap"
+    }
+
+    fn description(&self) -> &'static str {
+        "maps the ledger into memory and indexes it without copying"
+    }
+
+    fn validate(&self, summary: Input, txns: Input, options: &Options) -> Result<(), Error> {
+        validate(summary, txns, options).map(|_| ())
+    }
+
+    fn report(&self, summary: Input, txns: Input, options: &Options) -> Result<Report, Error> {
+        validate_all(summary, txns, options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::implementations::options::DuplicatePolicy;
+    use crate::implementations::{owned, resource_pairs};
+
+    #[test]
+    fn validate_all_agrees_with_owned() {
+        for duplicates in [DuplicatePolicy::Fail, DuplicatePolicy::Warn].iter() {
+            let options = Options {
+                check_balances: true,
+                duplicates: *duplicates,
+                record_rows: true,
+                ..Default::default()
+            };
+            for (summary, txns) in resource_pairs() {
+                let owned_report =
+                    owned::validate_all(&summary, &txns, &options).map_err(|e| e.to_string());
+                let mmap_report =
+                    validate_all(&summary, &txns, &options).map_err(|e| e.to_string());
+                assert_eq!(mmap_report, owned_report, "{} vs {}", summary, txns);
+            }
+        }
+    }
+
+    #[test]
+    fn fields_borrow_from_the_file_unless_unescaped() {
+        let text = "account,txn_id,amount,balance\n\
+                    a,1,-100,900\n\
+                    \"b\",1,100,100\n\
+                    \"say \"\"hi\"\"\",\"2\",5,5\n";
+        let ledger = parse(
+            text,
+            Path::new("txns"),
+            &Columns::default(),
+            &Scales::default(),
+        )
+        .unwrap();
+        let txns = ledger.transactions(text).collect::<Vec<_>>();
+        let fields = txns
+            .iter()
+            .map(|txn| (txn.account, txn.txn_id, txn.line))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            fields,
+            vec![("a", "1", 2), ("b", "1", 3), ("say \"hi\"", "2", 4)]
+        );
+        let within = |field: &str| text.as_bytes().as_ptr_range().contains(&field.as_ptr());
+        assert!(within(txns[0].account) && within(txns[1].account) && within(txns[2].txn_id));
+        assert!(!within(txns[2].account));
+        assert_eq!(ledger.unescaped, "say \"hi\"");
+    }
+
+    #[test]
+    fn invalid_utf8_names_the_line() {
+        let err = utf8(Path::new("txns"), b"account\na\n\xff\n").unwrap_err();
+        assert_eq!(err.to_string(), "txns: invalid UTF-8 on line 3");
+    }
+}
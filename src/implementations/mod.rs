@@ -1,2 +1,50 @@
+//! # Reconciliation
+//!
+//! Checks a summary of transfers against the transaction ledger they
+//! should have produced. [`Validation`] is the way in; the `owned`, `refs`,
+//! `mmap`, `spill` and `sorted` modules are implementations of the same
+//! algorithm, each available as a [`Validator`].
+//!
+
+pub mod amount;
+pub mod columns;
+pub mod config;
+pub mod dates;
+pub mod error;
+pub mod fx;
+pub mod generate;
+pub mod index;
+pub mod input;
+pub mod mmap;
+pub mod options;
+pub mod output;
 pub mod owned;
 pub mod refs;
+pub mod report;
+pub mod sorted;
+pub mod spill;
+pub mod store;
+pub mod validation;
+pub mod validator;
+
+pub use validation::Validation;
+pub use validator::{strategies, strategy, Validator};
+
+/// Every `summary*.csv` in `resources/` paired with its `txns*.csv`.
+#[cfg(test)]
+pub(crate) fn resource_pairs() -> Vec<(String, String)> {
+    let mut pairs: Vec<(String, String)> = std::fs::read_dir("resources")
+        .unwrap()
+        .filter_map(|entry| {
+            let name = entry.unwrap().file_name().into_string().unwrap();
+            let suffix = name.strip_prefix("summary")?.to_owned();
+            Some((
+                format!("resources/{}", name),
+                format!("resources/txns{}", suffix),
+            ))
+        })
+        .collect();
+    pairs.sort();
+    assert!(!pairs.is_empty());
+    pairs
+}
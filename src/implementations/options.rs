@@ -0,0 +1,219 @@
+//! # Validation Options
+//!
+//! Checks beyond the basic summary-to-ledger lookup are opt-in, and are
+//! switched on here.
+//!
+
+use std::path::PathBuf;
+
+use super::amount::{Money, Scales, Tolerance};
+use super::columns::Mapping;
+use super::dates::days_between;
+use super::error::Error;
+use super::fx::{Rates, FX_ROUNDING};
+use super::report::{Category, Finding, Severity};
+
+/// What to do when a key shows up on more than one row of an input.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Refuse to validate, since one row would silently mask another
+    #[default]
+    Fail,
+    /// Keep the first occurrence and report the rest as warnings
+    Warn,
+}
+
+impl DuplicatePolicy {
+    pub const NAMES: [&'static str; 2] = ["fail", "warn"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "fail" => Some(DuplicatePolicy::Fail),
+            "warn" => Some(DuplicatePolicy::Warn),
+            _ => None,
+        }
+    }
+
+    /// Apply the policy to the duplicates found while loading.
+    ///
+    /// Returns the findings to include in the report, or an error that
+    /// lists every occurrence. Repeated legs are an error under either
+    /// policy.
+    pub fn apply(self, duplicates: Vec<Finding>) -> Result<Vec<Finding>, Error> {
+        let (repeated, duplicates): (Vec<_>, Vec<_>) = duplicates
+            .into_iter()
+            .partition(|dup| dup.category == Category::RepeatedLeg);
+        if !repeated.is_empty() {
+            return Err(Error::RepeatedLegs(repeated));
+        }
+        if duplicates.is_empty() {
+            return Ok(duplicates);
+        }
+        match self {
+            DuplicatePolicy::Fail => Err(Error::Duplicates(duplicates)),
+            DuplicatePolicy::Warn => Ok(duplicates
+                .into_iter()
+                .map(|dup| Finding {
+                    severity: Severity::Warning,
+                    ..dup
+                })
+                .collect()),
+        }
+    }
+}
+
+/// Settings shared by every implementation.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Options {
+    /// Verify that each account's running balance follows from its
+    /// previous posting, in file order
+    pub check_balances: bool,
+    pub duplicates: DuplicatePolicy,
+    /// Header names to read each input's columns from
+    pub columns: Mapping,
+    /// How many decimal places amounts have in each currency
+    pub scales: Scales,
+    /// How far a ledger amount may be from the summary's and only be
+    /// reported as a warning
+    pub tolerance: Option<Tolerance>,
+    /// Exchange rates to convert legs whose ledger rows are in another
+    /// currency
+    pub rates: Rates,
+    /// How many days after the summary row's date a ledger row may post
+    /// before it's a late settlement. Only checked for rows where both
+    /// inputs have a date
+    pub settlement_days: Option<u32>,
+    /// Keep a note of every summary row in the report, not just those with
+    /// findings, e.g. to list passing rows in JUnit output
+    pub record_rows: bool,
+    /// How much memory the spill strategy may use, in bytes. Defaults to
+    /// [`DEFAULT_MEMORY_BUDGET`](super::spill::DEFAULT_MEMORY_BUDGET)
+    pub memory_budget: Option<u64>,
+    /// Where the spill strategy writes its partitions. Defaults to the
+    /// system temporary directory
+    pub spill_dir: Option<PathBuf>,
+    /// Where the owned strategy keeps its index of the ledger between runs,
+    /// so that it only parses newly appended rows
+    pub ledger_index: Option<PathBuf>,
+}
+
+impl Options {
+    /// Compare a summary leg's amount with its ledger row's, converting it
+    /// first if the two are in different currencies.
+    ///
+    /// Returns `None` if they match. Otherwise `mismatch` is called for a
+    /// finding about the leg and its row, and that is filled in with what's
+    /// wrong: the converted amount, the rate used, and whether the
+    /// difference is within the tolerance.
+    pub fn check_amount<F>(
+        &self,
+        leg: Money,
+        txn: Money,
+        date: &str,
+        mismatch: F,
+    ) -> Option<Finding>
+    where
+        F: FnOnce() -> Finding,
+    {
+        if leg.currency.is_empty() || txn.currency.is_empty() || leg.currency == txn.currency {
+            if leg.amount == txn.amount {
+                return None;
+            }
+            return Some(Finding {
+                category: Category::AmountMismatch,
+                severity: self.mismatch_severity(leg.amount, txn.amount),
+                expected: Some(leg.amount),
+                ..mismatch()
+            });
+        }
+        let rate = match self.rates.find(leg.currency, txn.currency, date) {
+            Some(rate) => rate,
+            None => {
+                return Some(Finding {
+                    category: Category::MissingRate,
+                    severity: Severity::Error,
+                    ..mismatch()
+                })
+            }
+        };
+        let expected = rate.convert(
+            leg.amount,
+            self.scales.scale(leg.currency),
+            self.scales.scale(txn.currency),
+        );
+        let severity = match expected {
+            Some(expected)
+                if (i128::from(expected) - i128::from(txn.amount)).abs()
+                    <= i128::from(FX_ROUNDING) =>
+            {
+                return None
+            }
+            Some(expected) => self.mismatch_severity(expected, txn.amount),
+            None => Severity::Error,
+        };
+        Some(Finding {
+            category: Category::AmountMismatch,
+            severity,
+            expected,
+            rate: Some(rate),
+            days_to_settle: None,
+            ..mismatch()
+        })
+    }
+
+    /// Compare a summary leg dated `date` with the ledger row it matched,
+    /// which posted on `posted`, describing every discrepancy.
+    ///
+    /// The amount and the settlement window are checked separately, so a
+    /// mismatch within the tolerance doesn't hide a late posting. `finding`
+    /// is called for a finding of the given category about the leg and its
+    /// row. No findings means the row matched.
+    pub fn check_posting<F>(
+        &self,
+        leg: Money,
+        txn: Money,
+        date: &str,
+        posted: &str,
+        finding: F,
+    ) -> Vec<Finding>
+    where
+        F: Fn(Category) -> Finding,
+    {
+        let mut findings = Vec::new();
+        findings.extend(self.check_amount(leg, txn, date, || finding(Category::AmountMismatch)));
+        findings.extend(self.check_settlement(date, posted, || finding(Category::LateSettlement)));
+        findings
+    }
+
+    /// Check that a ledger row posted on `posted` falls within the
+    /// settlement window of a summary row dated `date`.
+    ///
+    /// Returns `None` if there's no window, either date is missing, or the
+    /// row posted in time. Rows that posted before the summary date aren't
+    /// late, so they pass too.
+    pub fn check_settlement<F>(&self, date: &str, posted: &str, late: F) -> Option<Finding>
+    where
+        F: FnOnce() -> Finding,
+    {
+        let window = self.settlement_days?;
+        let days = days_between(date, posted)?;
+        if days <= i64::from(window) {
+            return None;
+        }
+        Some(Finding {
+            category: Category::LateSettlement,
+            severity: Severity::Error,
+            days_to_settle: Some(days),
+            ..late()
+        })
+    }
+
+    /// How serious it is for a ledger row to have `actual` where the summary
+    /// expects `expected`: only a warning if it's within the tolerance.
+    fn mismatch_severity(&self, expected: i64, actual: i64) -> Severity {
+        match self.tolerance {
+            Some(tolerance) if tolerance.allows(expected, actual) => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
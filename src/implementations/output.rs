@@ -0,0 +1,395 @@
+//! # Output
+//!
+//! How the outcome of a run is written out for whoever started it: as
+//! plain text for people, or as a JSON document for pipelines. A run can
+//! also be written as JUnit XML for CI dashboards, and its findings as a
+//! CSV that opens in a spreadsheet. Either way the [`Status`] decides the
+//! process exit code, so scripts can tell a reconciliation that found
+//! problems from one that could not run at all.
+//!
+
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use super::error::Error;
+use super::report::{Finding, Report, Severity};
+
+/// The columns of [`Run::to_discrepancy_csv`].
+pub const DISCREPANCY_COLUMNS: [&str; 9] = [
+    "txn_id",
+    "account",
+    "expected_amount",
+    "actual_amount",
+    "category",
+    "summary_line",
+    "txn_line",
+    "rate",
+    "days_to_settle",
+];
+
+/// How a run's outcome is written out.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+}
+
+impl Format {
+    pub const NAMES: [&'static str; 2] = ["text", "json"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(Format::Text),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+/// The overall result of a run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// Both inputs were read and they agree
+    Valid,
+    /// Both inputs were read, and at least one error-level finding was made
+    Findings,
+    /// An input could not be read, so nothing was reconciled
+    Failed,
+}
+
+impl Status {
+    pub fn name(self) -> &'static str {
+        match self {
+            Status::Valid => "valid",
+            Status::Findings => "findings",
+            Status::Failed => "error",
+        }
+    }
+
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Status::Valid => 0,
+            Status::Findings => 1,
+            Status::Failed => 2,
+        }
+    }
+}
+
+/// Everything known about one finished run.
+#[derive(Debug)]
+pub struct Run<'a> {
+    pub summary: &'a Path,
+    pub txns: &'a Path,
+    pub strategy: &'a str,
+    pub elapsed: Duration,
+    pub outcome: Result<Report, Error>,
+}
+
+impl<'a> Run<'a> {
+    pub fn status(&self) -> Status {
+        match &self.outcome {
+            Ok(report) if report.is_valid() => Status::Valid,
+            Ok(_) => Status::Findings,
+            Err(e) if !e.findings().is_empty() => Status::Findings,
+            Err(_) => Status::Failed,
+        }
+    }
+
+    /// Every finding made, whether the run collected them all or stopped
+    /// at the first.
+    pub fn findings(&self) -> &[Finding] {
+        match &self.outcome {
+            Ok(report) => &report.findings,
+            Err(e) => e.findings(),
+        }
+    }
+
+    /// The run as a JSON document.
+    ///
+    /// Row counts are `null` when the run stopped before counting them, and
+    /// `error` is only set when it could not run at all.
+    pub fn to_json(&self) -> String {
+        let rows = self.outcome.as_ref().ok().map(|report| {
+            json!({
+                "summary": report.summaries,
+                "txns": report.txns,
+                "legs": report.legs,
+                "matched": report.matched,
+            })
+        });
+        let error = match self.status() {
+            Status::Failed => self.outcome.as_ref().err().map(|e| e.to_string()),
+            _ => None,
+        };
+        let document = json!({
+            "status": self.status().name(),
+            "strategy": self.strategy,
+            "inputs": {
+                "summary": self.summary.display().to_string(),
+                "txns": self.txns.display().to_string(),
+            },
+            "rows": rows,
+            "elapsed_ms": self.elapsed.as_secs_f64() * 1000.0,
+            "findings": self.findings(),
+            "error": error,
+        });
+        serde_json::to_string_pretty(&document).expect("a run always serializes")
+    }
+
+    /// The run as a JUnit XML document.
+    ///
+    /// Each summary row is a test case, failed by any error-level findings
+    /// that point at its line; warnings are attached as output. Rows with
+    /// no findings are only listed if the run kept a note of them with
+    /// [`Options::record_rows`](super::options::Options::record_rows). Findings
+    /// tied only to the ledger, such as orphans, get a test case each. If
+    /// the inputs could not be read, the one test case is an error.
+    pub fn to_junit(&self) -> String {
+        let mut suite = Suite::default();
+        let mut by_line: HashMap<u64, Vec<&Finding>> = HashMap::new();
+        let mut unattached = Vec::new();
+        for finding in self.findings() {
+            match finding.summary_line {
+                Some(line) => by_line.entry(line).or_default().push(finding),
+                None => unattached.push(finding),
+            }
+        }
+
+        match &self.outcome {
+            Ok(report) => {
+                for row in report.rows.iter() {
+                    let findings = by_line.remove(&row.line).unwrap_or_default();
+                    let name = format!("{} (line {})", row.id, row.line);
+                    suite.case("summary", &name, &findings);
+                }
+            }
+            Err(e) if self.status() == Status::Failed => suite.error("inputs", "read", e),
+            Err(_) => {}
+        }
+        // Without a full report, findings are all that is known of the rows
+        let mut leftover = by_line.into_iter().collect::<Vec<_>>();
+        leftover.sort_by_key(|(line, _)| *line);
+        for (line, findings) in leftover {
+            let name = format!("{} (line {})", findings[0].txn_id, line);
+            suite.case("summary", &name, &findings);
+        }
+        for finding in unattached {
+            let name = match finding.txn_line {
+                Some(line) => format!("{}::{} (line {})", finding.account, finding.txn_id, line),
+                None => format!("{}::{}", finding.account, finding.txn_id),
+            };
+            suite.case("ledger", &name, &[finding]);
+        }
+
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <testsuites>\n  \
+             <testsuite name=\"reconciliation::{}\" tests=\"{}\" failures=\"{}\" \
+             errors=\"{}\" time=\"{:.3}\">\n{}  </testsuite>\n\
+             </testsuites>\n",
+            escape(self.strategy),
+            suite.tests,
+            suite.failures,
+            suite.errors,
+            self.elapsed.as_secs_f64(),
+            suite.cases
+        )
+    }
+
+    /// Every finding as CSV, with the columns in [`DISCREPANCY_COLUMNS`].
+    /// Values that don't apply to a finding are left empty.
+    pub fn to_discrepancy_csv(&self) -> String {
+        fn cell<T: ToString>(value: Option<T>) -> String {
+            value.map_or_else(String::new, |v| v.to_string())
+        }
+
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        let written = writer.write_record(DISCREPANCY_COLUMNS).and_then(|_| {
+            self.findings().iter().try_for_each(|finding| {
+                writer.write_record(&[
+                    finding.txn_id.clone(),
+                    finding.account.clone(),
+                    cell(finding.expected),
+                    cell(finding.actual),
+                    finding.category.to_string(),
+                    cell(finding.summary_line),
+                    cell(finding.txn_line),
+                    cell(finding.rate.as_ref()),
+                    cell(finding.days_to_settle),
+                ])
+            })
+        });
+        written.expect("writing to memory can't fail");
+        let bytes = writer.into_inner().expect("writing to memory can't fail");
+        String::from_utf8(bytes).expect("every cell is valid UTF-8")
+    }
+}
+
+/// The test cases of a JUnit suite, written out as they are added.
+#[derive(Default)]
+struct Suite {
+    cases: String,
+    tests: usize,
+    failures: usize,
+    errors: usize,
+}
+
+impl Suite {
+    fn case(&mut self, class: &str, name: &str, findings: &[&Finding]) {
+        self.tests += 1;
+        let _ = write!(
+            self.cases,
+            "    <testcase classname=\"{}\" name=\"{}\"",
+            class,
+            escape(name)
+        );
+        if findings.is_empty() {
+            self.cases.push_str("/>\n");
+            return;
+        }
+        self.cases.push_str(">\n");
+        let (errors, warnings): (Vec<&Finding>, Vec<&Finding>) = findings
+            .iter()
+            .partition(|finding| finding.severity == Severity::Error);
+        if !errors.is_empty() {
+            self.failures += 1;
+        }
+        for finding in errors {
+            let _ = writeln!(
+                self.cases,
+                "      <failure type=\"{}\" message=\"{}\"/>",
+                finding.category,
+                escape(&finding.to_string())
+            );
+        }
+        if !warnings.is_empty() {
+            self.cases.push_str("      <system-out>");
+            for finding in warnings {
+                let _ = writeln!(self.cases, "{}", escape(&finding.to_string()));
+            }
+            self.cases.push_str("</system-out>\n");
+        }
+        self.cases.push_str("    </testcase>\n");
+    }
+
+    fn error(&mut self, class: &str, name: &str, error: &Error) {
+        self.tests += 1;
+        self.errors += 1;
+        let _ = write!(
+            self.cases,
+            "    <testcase classname=\"{}\" name=\"{}\">\n      \
+             <error message=\"{}\"/>\n    </testcase>\n",
+            class,
+            escape(name),
+            escape(&error.to_string())
+        );
+    }
+}
+
+/// Escape text for use in XML attributes and content.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::implementations::options::Options;
+    use crate::implementations::owned;
+    use serde_json::Value;
+
+    fn run(summary: &'static str, txns: &'static str) -> Run<'static> {
+        let options = Options {
+            record_rows: true,
+            ..Default::default()
+        };
+        Run {
+            summary: Path::new(summary),
+            txns: Path::new(txns),
+            strategy: "owned",
+            elapsed: Duration::from_millis(5),
+            outcome: owned::validate_all(summary, txns, &options),
+        }
+    }
+
+    #[test]
+    fn json_lists_counts_and_findings() {
+        let run = run("resources/summary_bad.csv", "resources/txns_bad.csv");
+        assert_eq!(run.status(), Status::Findings);
+        let document: Value = serde_json::from_str(&run.to_json()).unwrap();
+        assert_eq!(document["status"], "findings");
+        assert_eq!(document["strategy"], "owned");
+        assert_eq!(document["inputs"]["txns"], "resources/txns_bad.csv");
+        assert_eq!(document["rows"]["summary"], 4);
+        assert_eq!(document["elapsed_ms"], 5.0);
+        let first = &document["findings"][0];
+        assert_eq!(first["category"], "amount_mismatch");
+        assert_eq!(first["severity"], "error");
+        assert_eq!(first["account"], "r");
+        assert_eq!(first["txn_id"], "2");
+        assert!(document["error"].is_null());
+    }
+
+    #[test]
+    fn unreadable_inputs_are_reported_as_errors() {
+        let run = run("resources/summary0.csv", "resources/nope.csv");
+        assert_eq!(run.status(), Status::Failed);
+        assert_eq!(run.status().exit_code(), 2);
+        let document: Value = serde_json::from_str(&run.to_json()).unwrap();
+        assert!(document["rows"].is_null());
+        assert!(document["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("resources/nope.csv"));
+    }
+
+    #[test]
+    fn junit_has_a_test_case_per_summary_row() {
+        let junit = run("resources/summary_bad.csv", "resources/txns_bad.csv").to_junit();
+        assert!(junit.contains(r#"tests="6" failures="5" errors="0""#));
+        assert!(junit.contains(r#"<testcase classname="summary" name="1 (line 2)"/>"#));
+        assert!(junit.contains(r#"<failure type="amount_mismatch""#));
+        assert!(junit.contains(r#"<testcase classname="ledger" name="z::9 (line 9)">"#));
+
+        let junit = run("resources/summary0.csv", "resources/nope.csv").to_junit();
+        assert!(junit.contains(r#"tests="1" failures="0" errors="1""#));
+    }
+
+    #[test]
+    fn discrepancy_csv_has_a_row_per_finding() {
+        let csv = run("resources/summary_bad.csv", "resources/txns_bad.csv").to_discrepancy_csv();
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next(),
+            Some(
+                "txn_id,account,expected_amount,actual_amount,category,summary_line,txn_line,rate,days_to_settle"
+            )
+        );
+        assert_eq!(lines.next(), Some("2,r,200,250,amount_mismatch,3,5,,"));
+        assert_eq!(lines.next(), Some("3,y,300,,missing,4,,,"));
+        assert_eq!(lines.next(), Some(":b,a:,-50,,missing,5,,,"));
+        assert_eq!(lines.count(), 2);
+    }
+
+    #[test]
+    fn duplicates_are_findings_rather_than_errors() {
+        let run = run("resources/summary_dup.csv", "resources/txns_dup.csv");
+        assert_eq!(run.status(), Status::Findings);
+        assert_eq!(run.findings().len(), 4);
+    }
+}
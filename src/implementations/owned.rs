@@ -4,38 +4,50 @@
 //! values within structs rather than references, in order to avoid
 //! the need for lifetimes (for demonstration purposes).
 //!
+//! Given a [`LedgerIndex`] to keep between runs, it only parses the rows
+//! appended to the ledger since the last run; see
+//! [`validate_all_indexed`].
+//!
 
-use csv;
-use csv::{Reader, StringRecord, StringRecordsIter};
-use std::collections::HashMap;
-use std::error::Error;
+use csv::StringRecord;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
+
+use super::amount::{Money, Scales, Total};
+use super::columns::{Columns, Layout, TXN_COLUMNS};
+use super::error::{line_of, Error};
+use super::index::{self, IndexedRows, LedgerIndex};
+use super::input::Input;
+use super::options::Options;
+use super::report::{Category, Finding, Report, Severity};
+use super::validator::Validator;
+
 pub trait FromRow {
-    fn from_row(row: &StringRecord) -> Result<Box<Self>, &'static str>;
+    /// Build a value from `row`, whose columns are laid out as in `layout`.
+    fn from_row(row: &StringRecord, layout: &Layout) -> Result<Box<Self>, Error>;
 }
 
 #[derive(Debug)]
 pub struct Transaction {
-    account: String,
-    txn_id: String,
-    amount: i64,
-    balance: i64,
+    pub(super) account: String,
+    pub(super) txn_id: String,
+    pub(super) amount: i64,
+    pub(super) balance: i64,
+    pub(super) currency: String,
+    pub(super) date: String,
+    pub(super) line: u64,
 }
 impl FromRow for Transaction {
-    fn from_row(row: &StringRecord) -> Result<Box<Self>, &'static str> {
+    fn from_row(row: &StringRecord, layout: &Layout) -> Result<Box<Self>, Error> {
         Ok(Transaction {
-            account: row.get(0).ok_or("no from")?.to_owned(),
-            txn_id: row.get(1).ok_or("no txn_id")?.to_owned(),
-            amount: row
-                .get(2)
-                .ok_or("no to")?
-                .parse::<i64>()
-                .map_err(|_| "bad txn")?,
-            balance: row
-                .get(3)
-                .ok_or("no amount")?
-                .parse::<i64>()
-                .map_err(|_| "bad balance")?,
+            account: layout.field(row, "account")?.to_owned(),
+            txn_id: layout.field(row, "txn_id")?.to_owned(),
+            amount: layout.amount(row, "amount")?,
+            balance: layout.amount(row, "balance")?,
+            currency: layout.optional(row, "currency")?.to_owned(),
+            date: layout.date(row)?.to_owned(),
+            line: line_of(row),
         }
         .into())
     }
@@ -47,7 +59,7 @@ impl Transaction {
     /// in the balance sheet is predictable from the transaction summary.
     ///
     /// # Example
-    /// ```
+    /// ```text
     /// # For a transfer of $500 from account A to account B
     /// let txn = TxnSummary{ id: "01", from: "a", to: "b", amount: 50000 }
     ///
@@ -56,52 +68,75 @@ impl Transaction {
     /// let txn_b_to_a = Transaction { account: "b", txn_id: "01", amount: 50000, balance: 10000 }
     ///
     /// # In order to easily find the txn history from the summary item,
-    /// # we create a hash based on the account and the txn_id
+    /// # we key it on the account and the txn_id
     ///
-    /// assert_eq!(txn_a_to_b.hash_key(), "a::01")
+    /// assert_eq!(txn_a_to_b.key(), ("a".into(), "01".into()))
     /// ```
-    fn hash_key(&self) -> String {
-        [&self.account, "::", &self.txn_id].concat()
+    fn key(&self) -> Key {
+        (self.account.clone(), self.txn_id.clone())
     }
 }
 
+/// A ledger row's account and txn_id. They're kept apart rather than
+/// joined into one string, since ids are free text and any separator could
+/// appear in them.
+pub type Key = (String, String);
+
+/// Ledger rows keyed by account and txn_id.
+pub type Ledger = HashMap<Key, Transaction>;
+
 #[derive(Debug)]
 pub struct PartialTxn {
-    account: String,
-    txn_id: String,
-    amount: i64,
-}
-impl PartialTxn {
-    fn eq_txn(&self, other: &Transaction) -> bool {
-        self.account == other.account && self.txn_id == other.txn_id && self.amount == other.amount
-    }
+    pub(super) account: String,
+    pub(super) txn_id: String,
+    pub(super) amount: i64,
+    pub(super) currency: String,
 }
 
 #[derive(Debug)]
 pub struct PartialTxnLookup {
-    hash_key: String,
-    partial_txn: PartialTxn,
+    key: Key,
+    pub(super) partial_txn: PartialTxn,
+    pub(super) summary_line: u64,
+    /// The summary row's date, which picks the rate to convert the leg at
+    pub(super) date: String,
 }
 
+/// A row of the summary: either a transfer with a debit and a credit leg,
+/// or a single leg of a transaction that spans several rows.
 #[derive(Debug)]
 pub struct TxnSummary {
     id: String,
-    from: String,
-    to: String,
-    amount: i64,
+    /// The account of a leg row, which along with `id` should be unique.
+    /// Empty for a transfer row, whose `id` should be unique on its own
+    account: String,
+    /// Each leg's account and signed amount
+    legs: Vec<(String, i64)>,
+    currency: String,
+    date: String,
+    line: u64,
 }
 
 impl FromRow for TxnSummary {
-    fn from_row(row: &StringRecord) -> Result<Box<Self>, &'static str> {
+    fn from_row(row: &StringRecord, layout: &Layout) -> Result<Box<Self>, Error> {
+        let id = layout.field(row, "id")?.to_owned();
+        let amount = layout.amount(row, "amount")?;
+        let (account, legs) = if layout.has("account") {
+            let account = layout.field(row, "account")?.to_owned();
+            (account.clone(), vec![(account, amount)])
+        } else {
+            let from = layout.field(row, "from")?.to_owned();
+            let to = layout.field(row, "to")?.to_owned();
+            let debit = layout.debit(row, "amount", amount)?;
+            (String::new(), vec![(from, debit), (to, amount)])
+        };
         Ok(TxnSummary {
-            id: row.get(0).ok_or("no from")?.to_owned(),
-            from: row.get(1).ok_or("no from")?.to_owned(),
-            to: row.get(2).ok_or("no to")?.to_owned(),
-            amount: row
-                .get(3)
-                .ok_or("no amount")?
-                .parse::<i64>()
-                .map_err(|_| "bad amount")?,
+            id,
+            account,
+            legs,
+            currency: layout.optional(row, "currency")?.to_owned(),
+            date: layout.date(row)?.to_owned(),
+            line: line_of(row),
         }
         .into())
     }
@@ -109,27 +144,35 @@ impl FromRow for TxnSummary {
 
 impl TxnSummary {
     fn partial_txns(&self) -> Vec<PartialTxnLookup> {
-        vec![
-            PartialTxnLookup {
-                hash_key: [&self.from, "::", &self.id].concat(),
+        self.legs
+            .iter()
+            .map(|(account, amount)| PartialTxnLookup {
+                key: (account.clone(), self.id.clone()),
                 partial_txn: PartialTxn {
-                    account: self.from.clone(),
+                    account: account.clone(),
                     txn_id: self.id.clone(),
-                    amount: -self.amount,
+                    amount: *amount,
+                    currency: self.currency.clone(),
                 },
-            },
-            PartialTxnLookup {
-                hash_key: [&self.to, "::", &self.id].concat(),
-                partial_txn: PartialTxn {
-                    account: self.to.clone(),
-                    txn_id: self.id.clone(),
-                    amount: self.amount,
-                },
-            },
-        ]
+                summary_line: self.line,
+                date: self.date.clone(),
+            })
+            .collect()
     }
 }
 
+/// Every summary leg, along with what's wrong with the summary on its own.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub legs: Vec<PartialTxnLookup>,
+    /// A [`Category::DuplicateSummaryId`] or [`Category::RepeatedLeg`]
+    /// finding for each repeated row
+    pub duplicates: Vec<Finding>,
+    /// A [`Category::Unbalanced`] finding for each transaction whose legs
+    /// don't sum to zero, in file order
+    pub unbalanced: Vec<Finding>,
+}
+
 // struct TxnSummaryRecords<'r>  {
 //     records: &'r StringRecordsIter<'r, std::fs::File>
 // }
@@ -155,55 +198,899 @@ impl TxnSummary {
 //     }
 // }
 
-pub fn load_summary<P: AsRef<Path>>(path: P) -> Result<Vec<PartialTxnLookup>, Box<Error>> {
-    let mut res = Vec::new();
-    let mut reader = Reader::from_path(path)?;
+/// Load every summary leg, whichever shape the summary has.
+///
+/// A transfer row is a repeat if its `id` has been seen before, and a leg
+/// row if its `id` and `account` have. A repeated leg is reported as a
+/// [`Category::RepeatedLeg`], which fails the run whatever the duplicate
+/// policy, since the ledger row it would match can only be matched once.
+pub fn load_summary<I: Into<Input>>(
+    input: I,
+    columns: &Columns,
+    scales: &Scales,
+) -> Result<Summary, Error> {
+    let mut summary = Summary::default();
+    let mut first_lines: HashMap<(String, String), u64> = HashMap::new();
+    // The first line and running total of each transaction's legs
+    let mut totals: HashMap<String, (u64, Total)> = HashMap::new();
+    let input = input.into();
+    let path = input.name().to_owned();
+    let mut reader = input.open_delimited(columns.delimiter())?;
+    let headers = reader.headers().map_err(|e| Error::csv(&path, e))?;
+    let layout = columns
+        .resolve_summary(headers)
+        .map_err(|e| e.in_file(&path))?
+        .with_scales(scales);
     for row in reader.records() {
-        TxnSummary::from_row(&row?)?
-            .partial_txns()
-            .into_iter()
-            .for_each(|partial_txn| res.push(partial_txn));
+        let row = row.map_err(|e| Error::csv(&path, e))?;
+        let txn_summary = TxnSummary::from_row(&row, &layout).map_err(|e| e.in_file(&path))?;
+        match first_lines.entry((txn_summary.id.clone(), txn_summary.account.clone())) {
+            Entry::Occupied(mut first) => {
+                // The first occurrence is reported alongside the first repeat
+                if *first.get() != 0 {
+                    summary
+                        .duplicates
+                        .push(summary_duplicate(&txn_summary, *first.get()));
+                    first.insert(0);
+                }
+                summary
+                    .duplicates
+                    .push(summary_duplicate(&txn_summary, txn_summary.line));
+            }
+            Entry::Vacant(slot) => {
+                slot.insert(txn_summary.line);
+            }
+        }
+        let (_, total) = totals
+            .entry(txn_summary.id.clone())
+            .or_insert_with(|| (txn_summary.line, Total::new(&txn_summary.currency)));
+        for (_, amount) in txn_summary.legs.iter() {
+            total.add(Money {
+                amount: *amount,
+                currency: &txn_summary.currency,
+            });
+        }
+        summary.legs.extend(txn_summary.partial_txns());
     }
-    Ok(res)
+    summary
+        .duplicates
+        .sort_by(|a, b| (&a.txn_id, a.summary_line).cmp(&(&b.txn_id, b.summary_line)));
+    summary.unbalanced = totals
+        .into_iter()
+        .filter_map(|(id, (line, total))| {
+            let sum = total.unbalanced()?;
+            Some(unbalanced_finding(id, line, sum))
+        })
+        .collect();
+    summary
+        .unbalanced
+        .sort_by_key(|finding| finding.summary_line);
+    Ok(summary)
 }
 
-pub fn load_transactions<P: AsRef<Path>>(
-    path: P,
-) -> Result<HashMap<String, Transaction>, Box<Error>> {
-    let mut res: HashMap<String, Transaction> = HashMap::new();
-    let mut reader = Reader::from_path(path)?;
+/// Load the ledger keyed by account and txn_id, along with a
+/// [`Category::DuplicateTxn`] finding for each row whose key appears more
+/// than once. Only the first row for a key is kept.
+pub fn load_transactions<I: Into<Input>>(
+    input: I,
+    columns: &Columns,
+    scales: &Scales,
+) -> Result<(Ledger, Vec<Finding>), Error> {
+    let mut res: Ledger = HashMap::new();
+    let mut reported: HashSet<Key> = HashSet::new();
+    let mut duplicates = Vec::new();
+    let input = input.into();
+    let path = input.name().to_owned();
+    let mut reader = input.open_delimited(columns.delimiter())?;
+    let headers = reader.headers().map_err(|e| Error::csv(&path, e))?;
+    let layout = columns
+        .resolve(headers, &TXN_COLUMNS)
+        .map_err(|e| e.in_file(&path))?
+        .with_scales(scales);
     for row in reader.records() {
-        let txn = Transaction::from_row(&row?)?;
-        res.insert(txn.hash_key(), *txn);
+        let row = row.map_err(|e| Error::csv(&path, e))?;
+        let txn = Transaction::from_row(&row, &layout).map_err(|e| e.in_file(&path))?;
+        match res.entry(txn.key()) {
+            Entry::Occupied(first) => {
+                if reported.insert(first.key().clone()) {
+                    duplicates.push(txn_duplicate(first.get()));
+                }
+                duplicates.push(txn_duplicate(&txn));
+            }
+            Entry::Vacant(slot) => {
+                slot.insert(*txn);
+            }
+        }
+    }
+    duplicates.sort_by(|a, b| {
+        (&a.account, &a.txn_id, a.txn_line).cmp(&(&b.account, &b.txn_id, b.txn_line))
+    });
+    Ok((res, duplicates))
+}
+
+fn summary_duplicate(txn_summary: &TxnSummary, line: u64) -> Finding {
+    Finding {
+        category: Category::summary_repeat(&txn_summary.account),
+        severity: Severity::Error,
+        account: txn_summary.account.clone(),
+        txn_id: txn_summary.id.clone(),
+        expected: None,
+        actual: None,
+        summary_line: Some(line),
+        txn_line: None,
+        rate: None,
+        days_to_settle: None,
     }
-    Ok(res)
+}
+
+/// A transaction whose legs sum to `total` rather than zero, or overflow
+/// if `total` is `None`.
+fn unbalanced_finding(id: String, line: u64, total: Option<i64>) -> Finding {
+    Finding {
+        category: Category::Unbalanced,
+        severity: Severity::Error,
+        account: String::new(),
+        txn_id: id,
+        expected: Some(0),
+        actual: total,
+        summary_line: Some(line),
+        txn_line: None,
+        rate: None,
+        days_to_settle: None,
+    }
+}
+
+fn txn_duplicate(txn: &Transaction) -> Finding {
+    Finding {
+        category: Category::DuplicateTxn,
+        severity: Severity::Error,
+        account: txn.account.clone(),
+        txn_id: txn.txn_id.clone(),
+        expected: None,
+        actual: Some(txn.amount),
+        summary_line: None,
+        txn_line: Some(txn.line),
+        rate: None,
+        days_to_settle: None,
+    }
+}
+
+/// Compare one summary leg against the ledger, describing any discrepancy.
+///
+/// An amount within the tolerance is still described, as a warning.
+pub fn check_lookup(
+    txn_lookup: &PartialTxnLookup,
+    txns: &Ledger,
+    options: &Options,
+) -> Vec<Finding> {
+    let partial_txn = &txn_lookup.partial_txn;
+    let finding = |category, txn: Option<&Transaction>| Finding {
+        category,
+        severity: Severity::Error,
+        account: partial_txn.account.clone(),
+        txn_id: partial_txn.txn_id.clone(),
+        expected: Some(partial_txn.amount),
+        actual: txn.map(|txn| txn.amount),
+        summary_line: Some(txn_lookup.summary_line),
+        txn_line: txn.map(|txn| txn.line),
+        rate: None,
+        days_to_settle: None,
+    };
+    match txns.get(&txn_lookup.key) {
+        Some(txn) => options.check_posting(
+            Money {
+                amount: partial_txn.amount,
+                currency: &partial_txn.currency,
+            },
+            Money {
+                amount: txn.amount,
+                currency: &txn.currency,
+            },
+            &txn_lookup.date,
+            &txn.date,
+            |category| finding(category, Some(txn)),
+        ),
+        None => vec![finding(Category::Missing, None)],
+    }
+}
+
+/// Ledger rows that no summary leg refers to, in file order.
+pub fn find_orphans<'t>(
+    summary_items: &[PartialTxnLookup],
+    txns: &'t Ledger,
+) -> Vec<&'t Transaction> {
+    let referenced: HashSet<&Key> = summary_items
+        .iter()
+        .map(|txn_lookup| &txn_lookup.key)
+        .collect();
+    let mut orphans: Vec<&Transaction> = txns
+        .iter()
+        .filter(|(key, _)| !referenced.contains(key))
+        .map(|(_, txn)| txn)
+        .collect();
+    orphans.sort_by_key(|txn| txn.line);
+    orphans
+}
+
+fn orphan_finding(txn: &Transaction) -> Finding {
+    Finding {
+        category: Category::Orphan,
+        severity: Severity::Error,
+        account: txn.account.clone(),
+        txn_id: txn.txn_id.clone(),
+        expected: None,
+        actual: Some(txn.amount),
+        summary_line: None,
+        txn_line: Some(txn.line),
+        rate: None,
+        days_to_settle: None,
+    }
+}
+
+/// The first posting in each account whose balance doesn't follow from
+/// the posting before it, ordered by ledger line.
+///
+/// Postings are ordered by their position in the ledger file, and each one
+/// is expected to satisfy `previous balance + amount == balance`.
+pub fn check_balances(txns: &Ledger) -> Vec<Finding> {
+    let mut by_account: HashMap<&str, Vec<&Transaction>> = HashMap::new();
+    for txn in txns.values() {
+        by_account.entry(&txn.account).or_default().push(txn);
+    }
+    let mut findings: Vec<Finding> = by_account
+        .values_mut()
+        .filter_map(|postings| {
+            postings.sort_by_key(|txn| txn.line);
+            postings.windows(2).find_map(|pair| {
+                let expected = pair[0].balance.checked_add(pair[1].amount);
+                if expected == Some(pair[1].balance) {
+                    return None;
+                }
+                Some(Finding {
+                    category: Category::BalanceBreak,
+                    severity: Severity::Error,
+                    account: pair[1].account.clone(),
+                    txn_id: pair[1].txn_id.clone(),
+                    expected,
+                    actual: Some(pair[1].balance),
+                    summary_line: None,
+                    txn_line: Some(pair[1].line),
+                    rate: None,
+                    days_to_settle: None,
+                })
+            })
+        })
+        .collect();
+    findings.sort_by_key(|finding| finding.txn_line);
+    findings
 }
 
 pub fn validate_summary(
-    summary_items: &Vec<PartialTxnLookup>,
-    txns: &HashMap<String, Transaction>,
-) -> Result<&'static str, Box<Error>> {
+    summary: &Summary,
+    txns: &Ledger,
+    options: &Options,
+) -> Result<&'static str, Error> {
+    let summary_items = &summary.legs;
     for txn_lookup in summary_items.iter() {
-        match txns.get(&txn_lookup.hash_key) {
-            Some(txn) => {
-                if !txn_lookup.partial_txn.eq_txn(txn) {
-                    return Err(format!(
-                        "Txn mismatch for lookup: {:?}, txn: {:?}",
-                        txn_lookup.partial_txn, txn
-                    )
-                    .into());
-                }
-            }
-            None => return Err(format!("Could not find txn: {:?}", txn_lookup.partial_txn).into()),
+        let findings = check_lookup(txn_lookup, txns, options);
+        if let Some(finding) = findings
+            .into_iter()
+            .find(|finding| finding.severity == Severity::Error)
+        {
+            return Err(finding.into());
+        }
+    }
+    if let Some(finding) = summary.unbalanced.first() {
+        return Err(finding.clone().into());
+    }
+    if let Some(txn) = find_orphans(summary_items, txns).first() {
+        return Err(orphan_finding(txn).into());
+    }
+    if options.check_balances {
+        if let Some(finding) = check_balances(txns).into_iter().next() {
+            return Err(finding.into());
         }
     }
     Ok("Valid!")
 }
 
+/// Like [`validate_summary`], but keep going past the first problem and
+/// return every finding.
+pub fn validate_summary_all(summary: &Summary, txns: &Ledger, options: &Options) -> Report {
+    let summary_items = &summary.legs;
+    let mut report = Report::new(txns.len());
+    let mut last_line = None;
+    for txn_lookup in summary_items.iter() {
+        // Legs from the same summary row are loaded next to each other
+        if last_line != Some(txn_lookup.summary_line) {
+            report.summaries += 1;
+            if options.record_rows {
+                report.row(&txn_lookup.partial_txn.txn_id, txn_lookup.summary_line);
+            }
+            last_line = Some(txn_lookup.summary_line);
+        }
+        report.record(check_lookup(txn_lookup, txns, options));
+    }
+    for finding in summary.unbalanced.iter() {
+        report.push(finding.clone());
+    }
+    for txn in find_orphans(summary_items, txns) {
+        report.push(orphan_finding(txn));
+    }
+    if options.check_balances {
+        check_balances(txns)
+            .into_iter()
+            .for_each(|finding| report.push(finding));
+    }
+    report
+}
+
+pub fn validate<S: Into<Input>, T: Into<Input>>(
+    summary: S,
+    txns: T,
+    options: &Options,
+) -> Result<&'static str, Error> {
+    let summary = load_summary(summary, &options.columns.summary, &options.scales)?;
+    let (txns, txn_duplicates) = load_transactions(txns, &options.columns.txns, &options.scales)?;
+    options.duplicates.apply(
+        summary
+            .duplicates
+            .iter()
+            .cloned()
+            .chain(txn_duplicates)
+            .collect(),
+    )?;
+    validate_summary(&summary, &txns, options)
+}
+
+pub fn validate_all<S: Into<Input>, T: Into<Input>>(
+    summary: S,
+    txns: T,
+    options: &Options,
+) -> Result<Report, Error> {
+    let summary = load_summary(summary, &options.columns.summary, &options.scales)?;
+    let (txns, txn_duplicates) = load_transactions(txns, &options.columns.txns, &options.scales)?;
+    validate_loaded_all(&summary, &txns, txn_duplicates, options)
+}
 
-pub fn validate<P1: AsRef<Path>, P2:AsRef<Path>>(summary_path: P1, txns_path: P2) -> Result<&'static str, Box<Error>> {
-    let txn_summaries = load_summary(summary_path)?;
-    let txns = load_transactions(txns_path)?;
-    validate_summary(&txn_summaries, &txns)
+/// Like [`validate_all`], for inputs that have already been loaded.
+/// `txn_duplicates` are the duplicates [`load_transactions`] found.
+pub fn validate_loaded_all(
+    summary: &Summary,
+    txns: &Ledger,
+    txn_duplicates: Vec<Finding>,
+    options: &Options,
+) -> Result<Report, Error> {
+    let duplicates = options.duplicates.apply(
+        summary
+            .duplicates
+            .iter()
+            .cloned()
+            .chain(txn_duplicates)
+            .collect(),
+    )?;
+    let mut report = validate_summary_all(summary, txns, options);
+    duplicates
+        .into_iter()
+        .for_each(|finding| report.push(finding));
+    Ok(report)
+}
+
+/// Like [`validate`], but read the ledger through the index at
+/// `index_path`. The findings are those of [`validate_all_indexed`], so
+/// this takes as long.
+pub fn validate_indexed<S: Into<Input>, T: Into<Input>>(
+    summary: S,
+    txns: T,
+    index_path: &Path,
+    options: &Options,
+) -> Result<(), Error> {
+    let report = validate_all_indexed(summary, txns, index_path, options)?;
+    match report
+        .findings
+        .into_iter()
+        .find(|finding| finding.severity == Severity::Error)
+    {
+        Some(finding) => Err(finding.into()),
+        None => Ok(()),
+    }
 }
 
+/// Like [`validate_all`], but read the ledger through the index at
+/// `index_path`, creating it if need be and saving it again once the
+/// ledger has been checked.
+///
+/// Only rows appended since the index was last saved are parsed in full;
+/// older rows are read at their offsets when a summary leg refers to
+/// them. A new row that repeats the key of any indexed row, old or new, is
+/// a duplicate. Orphans and balance breaks are only looked for among the
+/// new rows, since the older ones were checked by earlier runs.
+pub fn validate_all_indexed<S: Into<Input>, T: Into<Input>>(
+    summary: S,
+    txns: T,
+    index_path: &Path,
+    options: &Options,
+) -> Result<Report, Error> {
+    let summary = load_summary(summary, &options.columns.summary, &options.scales)?;
+    let txns = txns.into();
+    let path = txns.seekable_path()?;
+    let mut ledger_index = LedgerIndex::load(index_path, path)?;
+    let delimiter = options.columns.txns.delimiter();
+    let (headers, mut reader) = ledger_index.read_appended(path, delimiter)?;
+    let layout = options
+        .columns
+        .txns
+        .resolve(&headers, &TXN_COLUMNS)
+        .map_err(|e| e.in_file(path))?
+        .with_scales(&options.scales);
+    let mut rows = IndexedRows::open(path, delimiter)?;
+    let mut read_txn = |entry: index::Entry| -> Result<Transaction, Error> {
+        let row = rows.read(path, entry)?;
+        Transaction::from_row(row, &layout)
+            .map(|txn| *txn)
+            .map_err(|e| e.in_file(path))
+    };
+
+    let mut appended: Ledger = HashMap::new();
+    let mut txn_duplicates = Vec::new();
+    let mut reported: HashSet<Key> = HashSet::new();
+    let mut balance_breaks = Vec::new();
+    let mut broken: HashSet<String> = HashSet::new();
+    for row in reader.records() {
+        let row = row.map_err(|e| Error::csv(path, e))?;
+        let txn = Transaction::from_row(&row, &layout).map_err(|e| e.in_file(path))?;
+        let entry = index::Entry {
+            offset: row.position().map_or(0, |pos| pos.byte()),
+            line: txn.line,
+        };
+        if let Some(first) = ledger_index.insert(txn.key(), entry) {
+            if reported.insert(txn.key()) {
+                let finding = match appended.get(&txn.key()) {
+                    Some(first) => txn_duplicate(first),
+                    None => txn_duplicate(&read_txn(first)?),
+                };
+                txn_duplicates.push(finding);
+            }
+            txn_duplicates.push(txn_duplicate(&txn));
+            continue;
+        }
+        let previous = ledger_index.post(&txn.account, txn.balance);
+        let expected = previous.and_then(|balance| balance.checked_add(txn.amount));
+        if previous.is_some() && expected != Some(txn.balance) && broken.insert(txn.account.clone())
+        {
+            balance_breaks.push(Finding {
+                category: Category::BalanceBreak,
+                severity: Severity::Error,
+                account: txn.account.clone(),
+                txn_id: txn.txn_id.clone(),
+                expected,
+                actual: Some(txn.balance),
+                summary_line: None,
+                txn_line: Some(txn.line),
+                rate: None,
+                days_to_settle: None,
+            });
+        }
+        appended.insert(txn.key(), *txn);
+    }
+    txn_duplicates.sort_by(|a, b| {
+        (&a.account, &a.txn_id, a.txn_line).cmp(&(&b.account, &b.txn_id, b.txn_line))
+    });
+    let duplicates = options.duplicates.apply(
+        summary
+            .duplicates
+            .iter()
+            .cloned()
+            .chain(txn_duplicates)
+            .collect(),
+    )?;
+
+    // The new rows, and the older ones that the summary refers to
+    let mut txns = appended;
+    for txn_lookup in summary.legs.iter() {
+        if txns.contains_key(&txn_lookup.key) {
+            continue;
+        }
+        if let Some(entry) = ledger_index.get(&txn_lookup.key) {
+            txns.insert(txn_lookup.key.clone(), read_txn(entry)?);
+        }
+    }
+    let mut report = validate_summary_all(
+        &summary,
+        &txns,
+        &Options {
+            check_balances: false,
+            ..options.clone()
+        },
+    );
+    report.txns = ledger_index.rows();
+    if options.check_balances {
+        balance_breaks
+            .into_iter()
+            .for_each(|finding| report.push(finding));
+    }
+    duplicates
+        .into_iter()
+        .for_each(|finding| report.push(finding));
+    ledger_index.save(index_path)?;
+    Ok(report)
+}
+
+/// The owned implementation as a [`Validator`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Owned;
+
+impl Validator for Owned {
+    fn name(&self) -> &'static str {
+        "owned"
+    }
+
+    fn description(&self) -> &'static str {
+        "single-threaded, with every row copied into owned structs"
+    }
+
+    fn validate(&self, summary: Input, txns: Input, options: &Options) -> Result<(), Error> {
+        match &options.ledger_index {
+            Some(index_path) => validate_indexed(summary, txns, index_path, options),
+            None => validate(summary, txns, options).map(|_| ()),
+        }
+    }
+
+    fn report(&self, summary: Input, txns: Input, options: &Options) -> Result<Report, Error> {
+        match &options.ledger_index {
+            Some(index_path) => validate_all_indexed(summary, txns, index_path, options),
+            None => validate_all(summary, txns, options),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::error::Location;
+    use super::super::options::DuplicatePolicy;
+    use super::*;
+    use std::fs;
+    use std::io::Write;
+
+    #[test]
+    fn validate_all_reports_every_finding() {
+        let report = validate_all(
+            "resources/summary_bad.csv",
+            "resources/txns_bad.csv",
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(report.summaries, 4);
+        assert_eq!(report.legs, 8);
+        assert_eq!(report.matched, 5);
+        // The `a:` leg of txn `:b` must not be matched by the `a` row of
+        // txn `::b`, even though they'd join to the same `a:::b`
+        assert_eq!(report.count(Category::Missing), 2);
+        assert_eq!(report.count(Category::AmountMismatch), 1);
+        assert_eq!(report.count(Category::Orphan), 2);
+        assert_eq!(
+            report.findings[0],
+            Finding {
+                category: Category::AmountMismatch,
+                severity: Severity::Error,
+                account: "r".into(),
+                txn_id: "2".into(),
+                expected: Some(200),
+                actual: Some(250),
+                summary_line: Some(3),
+                txn_line: Some(5),
+                rate: None,
+                days_to_settle: None,
+            }
+        );
+        assert_eq!(
+            report.findings.last(),
+            Some(&Finding {
+                category: Category::Orphan,
+                severity: Severity::Error,
+                account: "z".into(),
+                txn_id: "9".into(),
+                expected: None,
+                actual: Some(-10),
+                summary_line: None,
+                txn_line: Some(9),
+                rate: None,
+                days_to_settle: None,
+            })
+        );
+    }
+
+    #[test]
+    fn check_balances_reports_first_break_per_account() {
+        let (txns, _) = load_transactions(
+            "resources/txns_balance.csv",
+            &Columns::default(),
+            &Scales::default(),
+        )
+        .unwrap();
+        let breaks = check_balances(&txns)
+            .into_iter()
+            .map(|finding| {
+                (
+                    finding.account,
+                    finding.expected,
+                    finding.actual,
+                    finding.txn_line,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            breaks,
+            vec![
+                ("a".to_owned(), Some(825), Some(800), Some(6)),
+                ("c".to_owned(), Some(60), Some(70), Some(9)),
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicates_fail_by_default() {
+        let err = validate(
+            "resources/summary_dup.csv",
+            "resources/txns_dup.csv",
+            &Options::default(),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("[duplicate_summary_id] account= txn_id=1 summary_line=2"));
+        assert!(err.contains("[duplicate_summary_id] account= txn_id=1 summary_line=4"));
+        assert!(err.contains("[duplicate_txn] account=a txn_id=1 actual=-100 txn_line=2"));
+        assert!(err.contains("[duplicate_txn] account=a txn_id=1 actual=-100 txn_line=5"));
+    }
+
+    #[test]
+    fn duplicates_can_be_downgraded_to_warnings() {
+        let options = Options {
+            duplicates: DuplicatePolicy::Warn,
+            ..Default::default()
+        };
+        let report = validate_all(
+            "resources/summary_dup.csv",
+            "resources/txns_dup.csv",
+            &options,
+        )
+        .unwrap();
+        assert!(report.is_valid());
+        assert_eq!(report.count(Category::DuplicateSummaryId), 2);
+        assert_eq!(report.count(Category::DuplicateTxn), 2);
+        assert!(report
+            .findings
+            .iter()
+            .all(|finding| finding.severity == Severity::Warning));
+    }
+
+    #[test]
+    fn parse_errors_point_at_the_bad_value() {
+        match validate(
+            "resources/summary_malformed.csv",
+            "resources/txns0.csv",
+            &Options::default(),
+        ) {
+            Err(Error::Parse { location, value }) => {
+                assert_eq!(
+                    location,
+                    Location {
+                        file: "resources/summary_malformed.csv".into(),
+                        line: 3,
+                        column: "amount".into(),
+                    }
+                );
+                assert_eq!(value, "abc");
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn missing_legs_are_not_found_errors() {
+        match validate(
+            "resources/summary_bad.csv",
+            "resources/txns0.csv",
+            &Options::default(),
+        ) {
+            Err(Error::NotFound(finding)) => {
+                assert_eq!(
+                    (finding.account.as_str(), finding.summary_line),
+                    ("x", Some(4))
+                )
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn columns_are_read_by_header_name() {
+        let mut options = Options::default();
+        assert_eq!(
+            validate(
+                "resources/summary0.csv",
+                "resources/txns_partner.csv",
+                &options
+            )
+            .unwrap_err()
+            .to_string(),
+            "resources/txns_partner.csv: no `amount` column in the header"
+        );
+        options.columns.txns.rename("amount", "value_cents");
+        let report = validate_all(
+            "resources/summary0.csv",
+            "resources/txns_partner.csv",
+            &options,
+        )
+        .unwrap();
+        assert!(report.is_valid());
+        assert_eq!(report.matched, 4);
+    }
+
+    #[test]
+    fn validate_fails_on_orphans() {
+        let err = validate(
+            "resources/summary0.csv",
+            "resources/txns_orphan.csv",
+            &Options::default(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().starts_with("Unreferenced txn"), "{}", err);
+    }
+
+    #[test]
+    fn legs_of_a_txn_must_balance() {
+        let options = Options {
+            duplicates: DuplicatePolicy::Warn,
+            ..Default::default()
+        };
+        let report = validate_all(
+            "resources/summary_legs.csv",
+            "resources/txns_legs.csv",
+            &options,
+        )
+        .unwrap();
+        assert_eq!(report.summaries, 11);
+        assert_eq!(report.legs, 11);
+        assert_eq!(report.matched, 11);
+        assert_eq!(
+            report.findings[0],
+            Finding {
+                category: Category::Unbalanced,
+                severity: Severity::Error,
+                account: "".into(),
+                txn_id: "3".into(),
+                expected: Some(0),
+                actual: Some(-10),
+                summary_line: Some(8),
+                txn_line: None,
+                rate: None,
+                days_to_settle: None,
+            }
+        );
+        assert_eq!(report.findings.len(), 1);
+        let err = validate(
+            "resources/summary_legs.csv",
+            "resources/txns_legs.csv",
+            &options,
+        )
+        .unwrap_err();
+        assert!(err.to_string().starts_with("Unbalanced txn"), "{}", err);
+    }
+
+    #[test]
+    fn validate_all_is_clean_on_generated_files() {
+        let report = validate_all(
+            "resources/summary1000.csv",
+            "resources/txns1000.csv",
+            &Options::default(),
+        )
+        .unwrap();
+        assert!(report.is_valid());
+        assert_eq!(report.legs, 2000);
+    }
+
+    #[test]
+    fn an_index_only_parses_appended_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let (ledger, index_path) = (dir.path().join("txns.csv"), dir.path().join("txns.index"));
+        fs::copy("resources/txns_bad.csv", &ledger).unwrap();
+        let options = Options {
+            check_balances: true,
+            duplicates: DuplicatePolicy::Warn,
+            ..Default::default()
+        };
+
+        // A new index covers the whole ledger, so nothing differs
+        let indexed = validate_all_indexed(
+            "resources/summary_bad.csv",
+            ledger.as_path(),
+            &index_path,
+            &options,
+        );
+        let full = validate_all("resources/summary_bad.csv", ledger.as_path(), &options);
+        assert_eq!(indexed.unwrap(), full.unwrap());
+
+        // Today's rows: one for a new txn, one whose balance doesn't follow
+        // from the last indexed posting, and one repeating an indexed key
+        let mut file = fs::OpenOptions::new().append(true).open(&ledger).unwrap();
+        file.write_all(b"q,4,-5,195\nx,4,5,700\nb,1,1,1101\n")
+            .unwrap();
+        let summary = dir.path().join("summary.csv");
+        fs::write(summary.as_path(), "id,from,to,amount\n4,q,x,5\n2,q,r,200\n").unwrap();
+        let report =
+            validate_all_indexed(summary.as_path(), ledger.as_path(), &index_path, &options)
+                .unwrap();
+        let found = report
+            .findings
+            .iter()
+            .map(|finding| (finding.category, finding.account.as_str(), finding.txn_line))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                // Read from the indexed part of the ledger
+                (Category::AmountMismatch, "r", Some(5)),
+                (Category::BalanceBreak, "x", Some(11)),
+                (Category::DuplicateTxn, "b", Some(3)),
+                (Category::DuplicateTxn, "b", Some(12)),
+            ]
+        );
+        assert_eq!(report.txns, 10);
+
+        // A ledger that's been rewritten rather than appended to is indexed
+        // from scratch
+        fs::copy("resources/txns_bad.csv", &ledger).unwrap();
+        let indexed = validate_all_indexed(
+            "resources/summary_bad.csv",
+            ledger.as_path(),
+            &index_path,
+            &options,
+        );
+        let full = validate_all("resources/summary_bad.csv", ledger.as_path(), &options);
+        assert_eq!(indexed.unwrap(), full.unwrap());
+    }
+
+    #[test]
+    fn appended_rows_repeating_an_indexed_key_are_duplicates() {
+        let dir = tempfile::tempdir().unwrap();
+        let (ledger, index_path) = (dir.path().join("txns.csv"), dir.path().join("txns.index"));
+        let summary = dir.path().join("summary.csv");
+        fs::write(
+            &ledger,
+            "account,txn_id,amount,balance\na,1,-5,95\nb,1,5,5\n",
+        )
+        .unwrap();
+        fs::write(&summary, "id,from,to,amount\n1,a,b,5\n").unwrap();
+        let run = || {
+            validate_all_indexed(
+                summary.as_path(),
+                ledger.as_path(),
+                &index_path,
+                &Options::default(),
+            )
+        };
+        assert!(run().unwrap().is_valid());
+
+        let mut file = fs::OpenOptions::new().append(true).open(&ledger).unwrap();
+        file.write_all(b"b,1,5,10\n").unwrap();
+        match run() {
+            Err(Error::Duplicates(findings)) => {
+                let lines = findings
+                    .iter()
+                    .map(|finding| (finding.category, finding.txn_line))
+                    .collect::<Vec<_>>();
+                assert_eq!(
+                    lines,
+                    vec![
+                        (Category::DuplicateTxn, Some(3)),
+                        (Category::DuplicateTxn, Some(4))
+                    ]
+                );
+            }
+            other => panic!("expected duplicates, got {:?}", other),
+        }
+        // Nothing was saved, so the next run finds them again
+        assert!(matches!(run(), Err(Error::Duplicates(_))));
+    }
+}
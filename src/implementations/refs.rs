@@ -2,200 +2,764 @@
 //!
 //! This implements the algorithm more efficiently using references.
 //!
+//! Nothing is copied per field: the ledger's accounts and txn_ids are
+//! appended to one buffer, and both its rows and their [`Key`]s borrow from
+//! that, while summary legs borrow from the summary rows they came from.
+//!
+//! Summary rows are independent of one another once the ledger has been
+//! loaded, so they are checked in parallel with rayon. Results are
+//! gathered back in file order, so the outcome is the same as the owned
+//! implementation's.
+//!
 
-use csv;
-use csv::{Reader, StringRecord, StringRecordsIter};
-use std::collections::HashMap;
-use std::error::Error;
+use csv::StringRecord;
+use rayon::prelude::*;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
 use std::path::Path;
 
+use super::amount::{Money, Scales, Total};
+use super::columns::{Columns, Layout, TXN_COLUMNS};
+use super::error::{line_of, Error};
+use super::input::Input;
+use super::options::Options;
+use super::report::{Category, Finding, Report, Severity};
+use super::validator::Validator;
+
+/// The key of a ledger row, borrowing its account and txn_id instead of
+/// joining them into a new `String`.
+///
+/// Keys compare and hash field by field, so no account and txn_id can be
+/// mistaken for another pair whatever characters they contain.
+///
+/// # Example
+/// ```text
+/// # For a transfer of $500 from account A to account B
+/// let txn = TxnSummary{ id: "01", from: "a", to: "b", amount: 50000 }
+///
+/// # The ledger row for the debit is found under
+/// Key { account: "a", txn_id: "01" }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(super) struct Key<'a> {
+    account: &'a str,
+    txn_id: &'a str,
+}
+
+/// A ledger row, borrowed from the text it was read from.
+#[derive(Debug)]
+pub(super) struct Transaction<'a> {
+    pub(super) account: &'a str,
+    pub(super) txn_id: &'a str,
+    pub(super) amount: i64,
+    pub(super) balance: i64,
+    pub(super) currency: &'a str,
+    pub(super) date: &'a str,
+    pub(super) line: u64,
+}
+
+impl<'a> Transaction<'a> {
+    fn key(&self) -> Key<'a> {
+        Key {
+            account: self.account,
+            txn_id: self.txn_id,
+        }
+    }
+}
+
+/// Where a ledger row's text sits in [`Ledger::text`].
 #[derive(Debug)]
-struct Transaction {
-    account: String,
-    txn_id: String,
+struct Row {
+    account: Range<usize>,
+    txn_id: Range<usize>,
     amount: i64,
     balance: i64,
+    currency: Range<usize>,
+    date: Range<usize>,
+    line: u64,
 }
-impl Transaction {
-    fn from_row(row: &StringRecord) -> Result<Self, &'static str> {
-        Ok(Transaction {
-            account: row.get(0).ok_or("no account")?.to_owned(),
-            txn_id: row.get(1).ok_or("no txn_id")?.to_owned(),
-            amount: row
-                .get(2)
-                .ok_or("no to")?
-                .parse::<i64>()
-                .map_err(|_| "bad txn")?,
-            balance: row
-                .get(3)
-                .ok_or("no amount")?
-                .parse::<i64>()
-                .map_err(|_| "bad balance")?,
-        })
+
+/// The whole ledger, with every account, txn_id, currency and date stored
+/// end to end in a single buffer rather than in a `String` each.
+#[derive(Debug, Default)]
+struct Ledger {
+    text: String,
+    rows: Vec<Row>,
+}
+
+impl Ledger {
+    fn push(&mut self, row: &StringRecord, layout: &Layout) -> Result<(), Error> {
+        let account = layout.field(row, "account")?;
+        let txn_id = layout.field(row, "txn_id")?;
+        let amount = layout.amount(row, "amount")?;
+        let balance = layout.amount(row, "balance")?;
+        let currency = layout.optional(row, "currency")?;
+        let date = layout.date(row)?;
+        let start = self.text.len();
+        self.text.push_str(account);
+        let middle = self.text.len();
+        self.text.push_str(txn_id);
+        let end = self.text.len();
+        self.text.push_str(currency);
+        let dated = self.text.len();
+        self.text.push_str(date);
+        self.rows.push(Row {
+            account: start..middle,
+            txn_id: middle..end,
+            amount,
+            balance,
+            currency: end..dated,
+            date: dated..self.text.len(),
+            line: line_of(row),
+        });
+        Ok(())
     }
 
-    /// Generate a unique hash key for this transfer.
-    ///
-    /// Generate a key in such a way such that the key for a given transaction
-    /// in the balance sheet is predictable from the transaction summary.
-    ///
-    /// # Example
-    /// ```
-    /// # For a transfer of $500 from account A to account B
-    /// let txn = TxnSummary{ id: "01", from: "a", to: "b", amount: 50000 }
-    ///
-    /// # We might find the following transactions in the transaction history
-    /// let txn_a_to_b = Transaction { account: "a", txn_id: "01", amount: -50000, balance: 10000 }
-    /// let txn_b_to_a = Transaction { account: "b", txn_id: "01", amount: 50000, balance: 10000 }
-    ///
-    /// # In order to easily find the txn history from the summary item,
-    /// # we create a hash based on the account and the txn_id
-    ///
-    /// assert_eq!(txn_a_to_b.hash_key(), "a::01")
-    /// ```
-    fn hash_key(&self) -> String {
-        [&self.account, "::", &self.txn_id].concat()
+    fn transactions(&self) -> impl Iterator<Item = Transaction<'_>> {
+        self.rows.iter().map(move |row| Transaction {
+            account: &self.text[row.account.clone()],
+            txn_id: &self.text[row.txn_id.clone()],
+            amount: row.amount,
+            balance: row.balance,
+            currency: &self.text[row.currency.clone()],
+            date: &self.text[row.date.clone()],
+            line: row.line,
+        })
     }
 }
 
+/// Ledger rows by key.
+pub(super) type Index<'a> = HashMap<Key<'a>, Transaction<'a>>;
+
 #[derive(Debug)]
 struct PartialTxn<'a> {
     account: &'a str,
     txn_id: &'a str,
     amount: i64,
+    currency: &'a str,
 }
 impl<'a> PartialTxn<'a> {
-    fn eq_txn(&self, other: &Transaction) -> bool {
-        self.account == other.account && self.txn_id == other.txn_id && self.amount == other.amount
+    fn key(&self) -> Key<'a> {
+        Key {
+            account: self.account,
+            txn_id: self.txn_id,
+        }
     }
 }
 
 #[derive(Debug)]
 struct PartialTxnLookup<'a> {
-    hash_key: String,
     partial_txn: PartialTxn<'a>,
+    summary_line: u64,
+    /// The summary row's date, which picks the rate to convert the leg at
+    date: &'a str,
 }
 
+/// A summary row: a transfer with a debit and a credit leg, or a single
+/// leg of a transaction that spans several rows.
 #[derive(Debug)]
-struct TxnSummary<'a> {
+pub(super) struct TxnSummary<'a> {
     id: &'a str,
-    from: &'a str,
-    to: &'a str,
-    amount: i64,
+    /// The account of a leg row; empty for a transfer row
+    account: &'a str,
+    /// Each leg's account and signed amount, of which the first
+    /// `leg_count` are used
+    legs: [(&'a str, i64); 2],
+    leg_count: usize,
+    currency: &'a str,
+    date: &'a str,
+    line: u64,
 }
 
 impl<'a> TxnSummary<'a> {
-    fn from_row(row: &'a StringRecord) -> Result<Self, &'static str> {
+    fn from_row(row: &'a StringRecord, layout: &Layout) -> Result<Self, Error> {
+        let id = layout.field(row, "id")?;
+        let amount = layout.amount(row, "amount")?;
+        let currency = layout.optional(row, "currency")?;
+        let date = layout.date(row)?;
+        let line = line_of(row);
+        if layout.has("account") {
+            let account = layout.field(row, "account")?;
+            return Ok(TxnSummary {
+                id,
+                account,
+                legs: [(account, amount), ("", 0)],
+                leg_count: 1,
+                currency,
+                date,
+                line,
+            });
+        }
+        let from = layout.field(row, "from")?;
+        let to = layout.field(row, "to")?;
+        let debit = layout.debit(row, "amount", amount)?;
         Ok(TxnSummary {
-            id: row.get(0).ok_or("no from")?,
-            from: row.get(1).ok_or("no from")?,
-            to: row.get(2).ok_or("no to")?,
-            amount: row
-                .get(3)
-                .ok_or("no amount")?
-                .parse::<i64>()
-                .map_err(|_| "bad amount")?,
+            id,
+            account: "",
+            legs: [(from, debit), (to, amount)],
+            leg_count: 2,
+            currency,
+            date,
+            line,
         })
     }
 }
 
 impl<'a> TxnSummary<'a> {
-    fn partial_txns(&'a self) -> Vec<PartialTxnLookup<'a>> {
-        vec![
-            PartialTxnLookup {
-                hash_key: [&self.from, "::", &self.id].concat(),
+    fn partial_txns(&self) -> impl Iterator<Item = PartialTxnLookup<'a>> + '_ {
+        self.legs[..self.leg_count]
+            .iter()
+            .map(move |&(account, amount)| PartialTxnLookup {
                 partial_txn: PartialTxn {
-                    account: &self.from,
-                    txn_id: &self.id,
-                    amount: -self.amount,
+                    account,
+                    txn_id: self.id,
+                    amount,
+                    currency: self.currency,
                 },
+                summary_line: self.line,
+                date: self.date,
+            })
+    }
+}
+
+/// Read the ledger into a single buffer. One record is reused for every
+/// row, so nothing is allocated per row beyond what the buffer grows by.
+fn load_transactions<I: Into<Input>>(
+    input: I,
+    columns: &Columns,
+    scales: &Scales,
+) -> Result<Ledger, Error> {
+    let input = input.into();
+    let path = input.name().to_owned();
+    let mut reader = input.open_delimited(columns.delimiter())?;
+    let headers = reader.headers().map_err(|e| Error::csv(&path, e))?;
+    let layout = columns
+        .resolve(headers, &TXN_COLUMNS)
+        .map_err(|e| e.in_file(&path))?
+        .with_scales(scales);
+    let mut ledger = Ledger::default();
+    let mut row = StringRecord::new();
+    while reader
+        .read_record(&mut row)
+        .map_err(|e| Error::csv(&path, e))?
+    {
+        ledger.push(&row, &layout).map_err(|e| e.in_file(&path))?;
+    }
+    Ok(ledger)
+}
+
+/// Index ledger rows by key, keeping only the first row for each key.
+///
+/// Returns a [`Category::DuplicateTxn`] finding for every row whose key
+/// appears more than once.
+pub(super) fn index<'a, I>(txns: I) -> (Index<'a>, Vec<Finding>)
+where
+    I: Iterator<Item = Transaction<'a>>,
+{
+    let mut res = Index::with_capacity(txns.size_hint().0);
+    let mut reported: HashSet<Key> = HashSet::new();
+    let mut duplicates = Vec::new();
+    for txn in txns {
+        match res.entry(txn.key()) {
+            Entry::Occupied(first) => {
+                if reported.insert(*first.key()) {
+                    duplicates.push(txn_duplicate(first.get()));
+                }
+                duplicates.push(txn_duplicate(&txn));
+            }
+            Entry::Vacant(slot) => {
+                slot.insert(txn);
+            }
+        }
+    }
+    duplicates.sort_by(|a, b| {
+        (&a.account, &a.txn_id, a.txn_line).cmp(&(&b.account, &b.txn_id, b.txn_line))
+    });
+    (res, duplicates)
+}
+
+/// A [`Category::DuplicateSummaryId`] finding for every transfer row whose
+/// `id` appears more than once, and a [`Category::RepeatedLeg`] one for every
+/// leg row whose `id` and `account` do.
+fn summary_duplicates(summaries: &[TxnSummary]) -> Vec<Finding> {
+    let mut lines_by_key: HashMap<(&str, &str), Vec<u64>> = HashMap::new();
+    for txn_summary in summaries.iter() {
+        lines_by_key
+            .entry((txn_summary.id, txn_summary.account))
+            .or_default()
+            .push(txn_summary.line);
+    }
+    let mut duplicates: Vec<Finding> = lines_by_key
+        .into_iter()
+        .filter(|(_, lines)| lines.len() > 1)
+        .flat_map(|((id, account), lines)| {
+            lines.into_iter().map(move |line| Finding {
+                category: Category::summary_repeat(account),
+                severity: Severity::Error,
+                account: account.to_owned(),
+                txn_id: id.to_owned(),
+                expected: None,
+                actual: None,
+                summary_line: Some(line),
+                txn_line: None,
+                rate: None,
+                days_to_settle: None,
+            })
+        })
+        .collect();
+    duplicates.sort_by(|a, b| (&a.txn_id, a.summary_line).cmp(&(&b.txn_id, b.summary_line)));
+    duplicates
+}
+
+/// A [`Category::Unbalanced`] finding for every transaction whose legs
+/// don't sum to zero, in the order the transactions first appear.
+fn unbalanced(summaries: &[TxnSummary]) -> Vec<Finding> {
+    let mut totals: HashMap<&str, (u64, Total)> = HashMap::new();
+    for txn_summary in summaries.iter() {
+        let (_, total) = totals
+            .entry(txn_summary.id)
+            .or_insert_with(|| (txn_summary.line, Total::new(txn_summary.currency)));
+        for &(_, amount) in txn_summary.legs[..txn_summary.leg_count].iter() {
+            total.add(Money {
+                amount,
+                currency: txn_summary.currency,
+            });
+        }
+    }
+    let mut findings: Vec<Finding> = totals
+        .into_iter()
+        .filter_map(|(id, (line, total))| {
+            Some(Finding {
+                category: Category::Unbalanced,
+                severity: Severity::Error,
+                account: String::new(),
+                txn_id: id.to_owned(),
+                expected: Some(0),
+                actual: total.unbalanced()?,
+                summary_line: Some(line),
+                txn_line: None,
+                rate: None,
+                days_to_settle: None,
+            })
+        })
+        .collect();
+    findings.sort_by_key(|finding| finding.summary_line);
+    findings
+}
+
+fn txn_duplicate(txn: &Transaction) -> Finding {
+    Finding {
+        category: Category::DuplicateTxn,
+        severity: Severity::Error,
+        account: txn.account.to_owned(),
+        txn_id: txn.txn_id.to_owned(),
+        expected: None,
+        actual: Some(txn.amount),
+        summary_line: None,
+        txn_line: Some(txn.line),
+        rate: None,
+        days_to_settle: None,
+    }
+}
+
+/// Compare one summary leg against the ledger, describing any discrepancy.
+fn check_lookup(txn_lookup: &PartialTxnLookup, txns: &Index, options: &Options) -> Vec<Finding> {
+    let partial_txn = &txn_lookup.partial_txn;
+    let finding = |category, txn: Option<&Transaction>| Finding {
+        category,
+        severity: Severity::Error,
+        account: partial_txn.account.to_owned(),
+        txn_id: partial_txn.txn_id.to_owned(),
+        expected: Some(partial_txn.amount),
+        actual: txn.map(|txn| txn.amount),
+        summary_line: Some(txn_lookup.summary_line),
+        txn_line: txn.map(|txn| txn.line),
+        rate: None,
+        days_to_settle: None,
+    };
+    match txns.get(&partial_txn.key()) {
+        Some(txn) => options.check_posting(
+            Money {
+                amount: partial_txn.amount,
+                currency: partial_txn.currency,
             },
-            PartialTxnLookup {
-                hash_key: [&self.to, "::", &self.id].concat(),
-                partial_txn: PartialTxn {
-                    account: &self.to,
-                    txn_id: &self.id,
-                    amount: self.amount,
-                },
+            Money {
+                amount: txn.amount,
+                currency: txn.currency,
             },
-        ]
+            txn_lookup.date,
+            txn.date,
+            |category| finding(category, Some(txn)),
+        ),
+        None => vec![finding(Category::Missing, None)],
     }
 }
 
-fn load_transactions<P: AsRef<Path>>(
-    path: P,
-    res: &mut HashMap<String, Transaction>,
-) -> Result<(), Box<Error>> {
-    let mut reader = Reader::from_path(path)?;
+/// Read every summary row up front, so they can be handed out to threads,
+/// along with where each column sits in them.
+pub(super) fn load_summary_rows<I: Into<Input>>(
+    input: I,
+    columns: &Columns,
+    scales: &Scales,
+) -> Result<(Vec<StringRecord>, Layout), Error> {
+    let input = input.into();
+    let path = input.name().to_owned();
+    let mut reader = input.open_delimited(columns.delimiter())?;
+    let headers = reader.headers().map_err(|e| Error::csv(&path, e))?;
+    let layout = columns
+        .resolve_summary(headers)
+        .map_err(|e| e.in_file(&path))?
+        .with_scales(scales);
+    let mut rows = Vec::new();
     for row in reader.records() {
-        let txn = Transaction::from_row(&row?)?;
-        res.insert(txn.hash_key(), txn);
+        rows.push(row.map_err(|e| Error::csv(&path, e))?);
+    }
+    Ok((rows, layout))
+}
+
+/// Parse the summary rows in parallel, failing on the first bad row in
+/// file order.
+pub(super) fn parse_summaries<'r>(
+    rows: &'r [StringRecord],
+    layout: &Layout,
+    path: &Path,
+) -> Result<Vec<TxnSummary<'r>>, Error> {
+    rows.par_iter()
+        .map(|row| TxnSummary::from_row(row, layout))
+        .collect::<Vec<Result<TxnSummary, Error>>>()
+        .into_iter()
+        .collect::<Result<Vec<TxnSummary>, Error>>()
+        .map_err(|e| e.in_file(path))
+}
+
+/// Check the legs of a summary row, failing on the first discrepancy.
+fn validate_row(txn_summary: &TxnSummary, txns: &Index, options: &Options) -> Result<(), Error> {
+    for txn_lookup in txn_summary.partial_txns() {
+        let findings = check_lookup(&txn_lookup, txns, options);
+        if let Some(finding) = findings
+            .into_iter()
+            .find(|finding| finding.severity == Severity::Error)
+        {
+            return Err(finding.into());
+        }
     }
     Ok(())
 }
 
-fn validate_lookup(
-    txn_lookup: &PartialTxnLookup,
-    txns: &HashMap<String, Transaction>,
-) -> Result<&'static str, Box<Error>> {
-    match txns.get(&txn_lookup.hash_key) {
-        Some(txn) => {
-            if !txn_lookup.partial_txn.eq_txn(txn) {
-                return Err(format!(
-                    "Txn mismatch for lookup: {:?}, txn: {:?}",
-                    txn_lookup.partial_txn, txn
-                )
-                .into());
-            }
+/// Check the legs of a summary row, collecting every discrepancy.
+fn validate_row_all(txn_summary: &TxnSummary, txns: &Index, options: &Options) -> Report {
+    let mut report = Report {
+        summaries: 1,
+        ..Default::default()
+    };
+    if options.record_rows {
+        report.row(txn_summary.id, txn_summary.line);
+    }
+    for txn_lookup in txn_summary.partial_txns() {
+        report.record(check_lookup(&txn_lookup, txns, options));
+    }
+    report
+}
+
+/// The ledger key of every summary leg, whether or not it exists.
+///
+/// Built on one thread, since merging a set per thread costs more than
+/// hashing the keys does.
+fn referenced_keys<'a>(summaries: &[TxnSummary<'a>]) -> HashSet<Key<'a>> {
+    let mut keys = HashSet::with_capacity(summaries.len() * 2);
+    for txn_summary in summaries.iter() {
+        for txn_lookup in txn_summary.partial_txns() {
+            keys.insert(txn_lookup.partial_txn.key());
+        }
+    }
+    keys
+}
+
+/// Ledger rows that no summary leg refers to, in file order.
+fn find_orphans<'t, 'a>(
+    referenced: &HashSet<Key<'a>>,
+    txns: &'t Index<'a>,
+) -> Vec<&'t Transaction<'a>> {
+    let mut orphans: Vec<&Transaction> = txns
+        .iter()
+        .filter(|(key, _)| !referenced.contains(*key))
+        .map(|(_, txn)| txn)
+        .collect();
+    orphans.sort_by_key(|txn| txn.line);
+    orphans
+}
+
+/// The first posting in each account whose balance doesn't follow from
+/// the posting before it, ordered by ledger line.
+///
+/// Postings are ordered by their position in the ledger file, and each one
+/// is expected to satisfy `previous balance + amount == balance`.
+fn check_balances(txns: &Index) -> Vec<Finding> {
+    let mut by_account: HashMap<&str, Vec<&Transaction>> = HashMap::new();
+    for txn in txns.values() {
+        by_account.entry(txn.account).or_default().push(txn);
+    }
+    let mut findings: Vec<Finding> = by_account
+        .values_mut()
+        .filter_map(|postings| {
+            postings.sort_by_key(|txn| txn.line);
+            postings.windows(2).find_map(|pair| {
+                let expected = pair[0].balance.checked_add(pair[1].amount);
+                if expected == Some(pair[1].balance) {
+                    return None;
+                }
+                Some(Finding {
+                    category: Category::BalanceBreak,
+                    severity: Severity::Error,
+                    account: pair[1].account.to_owned(),
+                    txn_id: pair[1].txn_id.to_owned(),
+                    expected,
+                    actual: Some(pair[1].balance),
+                    summary_line: None,
+                    txn_line: Some(pair[1].line),
+                    rate: None,
+                    days_to_settle: None,
+                })
+            })
+        })
+        .collect();
+    findings.sort_by_key(|finding| finding.txn_line);
+    findings
+}
+
+fn orphan_finding(txn: &Transaction) -> Finding {
+    Finding {
+        category: Category::Orphan,
+        severity: Severity::Error,
+        account: txn.account.to_owned(),
+        txn_id: txn.txn_id.to_owned(),
+        expected: None,
+        actual: Some(txn.amount),
+        summary_line: None,
+        txn_line: Some(txn.line),
+        rate: None,
+        days_to_settle: None,
+    }
+}
+
+pub fn validate<S: Into<Input>, T: Into<Input>>(
+    summary: S,
+    ledger: T,
+    options: &Options,
+) -> Result<&'static str, Error> {
+    let summary = summary.into();
+    let summary_path = summary.name().to_owned();
+    let (rows, layout) = load_summary_rows(summary, &options.columns.summary, &options.scales)?;
+    let summaries = parse_summaries(&rows, &layout, &summary_path)?;
+    let ledger = load_transactions(ledger, &options.columns.txns, &options.scales)?;
+    let (txns, txn_duplicates) = index(ledger.transactions());
+    check(&summaries, &txns, txn_duplicates, options)
+}
+
+/// Check parsed summary rows against an indexed ledger, stopping at the
+/// first problem.
+pub(super) fn check(
+    summaries: &[TxnSummary],
+    txns: &Index,
+    txn_duplicates: Vec<Finding>,
+    options: &Options,
+) -> Result<&'static str, Error> {
+    options.duplicates.apply(
+        summary_duplicates(summaries)
+            .into_iter()
+            .chain(txn_duplicates)
+            .collect(),
+    )?;
+
+    // `find_first` returns the earliest failure in file order, no matter
+    // which thread happened to find it first.
+    let failure = summaries
+        .par_iter()
+        .map(|txn_summary| validate_row(txn_summary, txns, options))
+        .find_first(|res| res.is_err());
+    if let Some(Err(e)) = failure {
+        return Err(e);
+    }
+    if let Some(finding) = unbalanced(summaries).into_iter().next() {
+        return Err(finding.into());
+    }
+    let referenced = referenced_keys(summaries);
+    if let Some(txn) = find_orphans(&referenced, txns).first() {
+        return Err(orphan_finding(txn).into());
+    }
+    if options.check_balances {
+        if let Some(finding) = check_balances(txns).into_iter().next() {
+            return Err(finding.into());
         }
-        None => return Err(format!("Could not find txn: {:?}", txn_lookup.partial_txn).into()),
     }
     Ok("Valid!")
 }
 
+/// Walk every summary row and collect all findings, rather than stopping
+/// at the first one.
+pub fn validate_all<S: Into<Input>, T: Into<Input>>(
+    summary: S,
+    ledger: T,
+    options: &Options,
+) -> Result<Report, Error> {
+    let summary = summary.into();
+    let summary_path = summary.name().to_owned();
+    let (rows, layout) = load_summary_rows(summary, &options.columns.summary, &options.scales)?;
+    let summaries = parse_summaries(&rows, &layout, &summary_path)?;
+    let ledger = load_transactions(ledger, &options.columns.txns, &options.scales)?;
+    let (txns, txn_duplicates) = index(ledger.transactions());
+    check_all(&summaries, &txns, txn_duplicates, options)
+}
 
-use std::thread;
-use std::sync::mpsc;
-use rayon::prelude::*;
+/// Like [`check`], but collect every finding.
+pub(super) fn check_all(
+    summaries: &[TxnSummary],
+    txns: &Index,
+    txn_duplicates: Vec<Finding>,
+    options: &Options,
+) -> Result<Report, Error> {
+    let duplicates = options.duplicates.apply(
+        summary_duplicates(summaries)
+            .into_iter()
+            .chain(txn_duplicates)
+            .collect(),
+    )?;
 
+    let row_reports: Vec<Report> = summaries
+        .par_iter()
+        .map(|txn_summary| validate_row_all(txn_summary, txns, options))
+        .collect();
+    let mut report = Report::new(txns.len());
+    for row_report in row_reports {
+        report.merge(row_report);
+    }
+    for finding in unbalanced(summaries) {
+        report.push(finding);
+    }
+    let referenced = referenced_keys(summaries);
+    for txn in find_orphans(&referenced, txns) {
+        report.push(orphan_finding(txn));
+    }
+    if options.check_balances {
+        check_balances(txns)
+            .into_iter()
+            .for_each(|finding| report.push(finding));
+    }
+    duplicates
+        .into_iter()
+        .for_each(|finding| report.push(finding));
+    Ok(report)
+}
+
+/// The references implementation as a [`Validator`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Refs;
+
+impl Validator for Refs {
+    fn name(&self) -> &'static str {
+        "refs"
+    }
+
+    fn description(&self) -> &'static str {
+        "summary rows borrowed in place and checked in parallel"
+    }
+
+    fn validate(&self, summary: Input, txns: Input, options: &Options) -> Result<(), Error> {
+        validate(summary, txns, options).map(|_| ())
+    }
+
+    fn report(&self, summary: Input, txns: Input, options: &Options) -> Result<Report, Error> {
+        validate_all(summary, txns, options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::options::DuplicatePolicy;
+    use super::*;
+    use crate::implementations::{owned, resource_pairs};
+    use proptest::prelude::*;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    fn hash(key: &Key) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        hasher.finish()
+    }
 
-pub fn validate<P1: AsRef<Path>, P2: AsRef<Path>>(
-    summary_path: P1,
-    txns_path: P2,
-) -> Result<&'static str, Box<Error>> {
-    let mut txns = HashMap::new();
-    load_transactions(txns_path, &mut txns)?;
-
-    let (tx, rx) = mpsc::channel::<Result<&'static str, &'static str>>();
-
-    let mut summary_reader = Reader::from_path(summary_path)?;
-    // for row in summary_reader.records() {
-    //     // for partial_txn in TxnSummary::from_row(&row?)?.partial_txns().into_par_iter() {
-    //     //     validate_lookup(&partial_txn, &txns)?;
-    //     // }
-    //     TxnSummary::from_row(&row?)?.partial_txns().par_iter()
-    //         .map(|partial_txn|
-
-    //         )
-    //         validate_lookup(&partial_txn, &txns)?;
-    // }
-    // summary_reader.records().into_iter().par_iter()
-    //     .map(|row| {
-
-    //     })
-    // for row in summary_reader.records() {
-    //     let row_tx = tx.clone();
-    //     thread::spawn(move || {
-    //         let txn_summary = TxnSummary::from_row(&row?);
-    //         for partial_txn in txn_summary.partial_txns().into_iter() {
-    //             if let Err(_) = validate_lookup(&partial_txn, &txns) {
-    //                 row_tx.send(Err("err")).unwrap();
-    //             } else { row_tx.send(Ok("valid")).unwrap(); };
-    //         }
-    //     });
-    //     // for partial_txn in TxnSummary::from_row(&row?)?.partial_txns().into_iter() {
-    //     //     validate_lookup(&partial_txn, &txns)?;
-    //     // }
-    // }
-    Ok("valid")
+    proptest! {
+        #[test]
+        fn keys_match_only_when_both_fields_do(
+            left in ("[a:]{0,6}", "[a:]{0,6}"),
+            right in ("[a:]{0,6}", "[a:]{0,6}"),
+        ) {
+            let (left_key, right_key) = (
+                Key { account: &left.0, txn_id: &left.1 },
+                Key { account: &right.0, txn_id: &right.1 },
+            );
+            prop_assert_eq!(left_key == right_key, left == right);
+            if left == right {
+                prop_assert_eq!(hash(&left_key), hash(&right_key));
+            }
+        }
+
+        #[test]
+        fn moving_a_separator_between_fields_changes_the_key(
+            account in ".{0,8}",
+            txn_id in ".{0,8}",
+            separator in "[:|/ ]{1,3}",
+        ) {
+            // Both of these join to the same string with no separator
+            let moved_left = [account.as_str(), separator.as_str()].concat();
+            let moved_right = [separator.as_str(), txn_id.as_str()].concat();
+            prop_assert_ne!(
+                Key { account: &moved_left, txn_id: &txn_id },
+                Key { account: &account, txn_id: &moved_right }
+            );
+        }
+    }
+
+    #[test]
+    fn validate_agrees_with_owned() {
+        let options = Options {
+            check_balances: true,
+            ..Default::default()
+        };
+        for (summary, txns) in resource_pairs() {
+            let owned_res = owned::validate(&summary, &txns, &options).map_err(|e| e.to_string());
+            let refs_res = validate(&summary, &txns, &options).map_err(|e| e.to_string());
+            assert_eq!(owned_res, refs_res, "{} / {}", summary, txns);
+        }
+    }
+
+    #[test]
+    fn validate_all_agrees_with_owned() {
+        for duplicates in [DuplicatePolicy::Fail, DuplicatePolicy::Warn].iter() {
+            let options = Options {
+                check_balances: true,
+                duplicates: *duplicates,
+                ..Default::default()
+            };
+            for (summary, txns) in resource_pairs() {
+                let owned_report =
+                    owned::validate_all(&summary, &txns, &options).map_err(|e| e.to_string());
+                let refs_report =
+                    validate_all(&summary, &txns, &options).map_err(|e| e.to_string());
+                assert_eq!(owned_report, refs_report, "{} / {}", summary, txns);
+            }
+        }
+    }
+
+    #[test]
+    fn validate_reports_first_failure_in_file_order() {
+        let err = validate(
+            "resources/summary_bad.csv",
+            "resources/txns_bad.csv",
+            &Options::default(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().starts_with("Txn mismatch"), "{}", err);
+    }
 }
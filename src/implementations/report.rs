@@ -0,0 +1,275 @@
+//! # Reconciliation Report
+//!
+//! The plain validators stop at the first problem they see. The report
+//! produced here is built by walking the whole summary instead, so one
+//! run lists every discrepancy along with where to find it in both files.
+//!
+
+use serde::Serialize;
+use std::fmt;
+
+use super::fx::Rate;
+
+/// How serious a [`Finding`] is.
+///
+/// Only errors make a report invalid; warnings are listed so that they
+/// can be followed up, but don't fail the run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// The kind of discrepancy a [`Finding`] describes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Category {
+    /// The summary expects a ledger row that does not exist
+    Missing,
+    /// The ledger row exists, but its amount differs from the summary
+    AmountMismatch,
+    /// The leg and its ledger row are in different currencies, with no
+    /// rate between them on the summary row's date
+    MissingRate,
+    /// The ledger row posted more days after the summary row's date than
+    /// the settlement window allows
+    LateSettlement,
+    /// The legs of a summary transaction don't sum to zero
+    Unbalanced,
+    /// A ledger row that no summary leg accounts for
+    Orphan,
+    /// The first posting in an account whose balance doesn't follow from
+    /// the previous posting
+    BalanceBreak,
+    /// One occurrence of an account and txn_id that appear together on more
+    /// than one ledger row
+    DuplicateTxn,
+    /// One occurrence of an `id` that appears on more than one summary row
+    /// of transfers
+    DuplicateSummaryId,
+    /// One of the rows of a summary with one row per leg that give a
+    /// transaction more than one leg on the same account. Legs aren't summed,
+    /// so such a summary is rejected whatever the duplicate policy
+    RepeatedLeg,
+    /// A row whose `txn_id` sorts before the row above it, in an input that
+    /// was expected to be sorted
+    OutOfOrder,
+}
+
+impl Category {
+    pub const ALL: [Category; 11] = [
+        Category::Missing,
+        Category::AmountMismatch,
+        Category::MissingRate,
+        Category::LateSettlement,
+        Category::Unbalanced,
+        Category::Orphan,
+        Category::BalanceBreak,
+        Category::DuplicateTxn,
+        Category::DuplicateSummaryId,
+        Category::RepeatedLeg,
+        Category::OutOfOrder,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Category::Missing => "missing",
+            Category::AmountMismatch => "amount_mismatch",
+            Category::MissingRate => "missing_rate",
+            Category::LateSettlement => "late_settlement",
+            Category::Unbalanced => "unbalanced",
+            Category::Orphan => "orphan",
+            Category::BalanceBreak => "balance_break",
+            Category::DuplicateTxn => "duplicate_txn",
+            Category::DuplicateSummaryId => "duplicate_summary_id",
+            Category::RepeatedLeg => "repeated_leg",
+            Category::OutOfOrder => "out_of_order",
+        }
+    }
+}
+
+impl Category {
+    /// What a summary row that repeats an earlier one is: a repeated leg if
+    /// it's a leg row, with an `account`, and a duplicate id otherwise.
+    pub fn summary_repeat(account: &str) -> Category {
+        match account.is_empty() {
+            true => Category::DuplicateSummaryId,
+            false => Category::RepeatedLeg,
+        }
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A single discrepancy between the summary and the transaction ledger.
+///
+/// Line numbers are 1-based and count the header, so they match what a
+/// text editor shows for the offending row.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Finding {
+    pub category: Category,
+    pub severity: Severity,
+    pub account: String,
+    pub txn_id: String,
+    pub expected: Option<i64>,
+    pub actual: Option<i64>,
+    pub summary_line: Option<u64>,
+    pub txn_line: Option<u64>,
+    /// The rate the leg was converted at, when it's in a different currency
+    /// from its ledger row. `expected` is then the converted amount
+    pub rate: Option<Rate>,
+    /// How many days after the summary row's date the ledger row posted,
+    /// for late settlements
+    pub days_to_settle: Option<i64>,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.severity == Severity::Warning {
+            write!(f, "warning: ")?;
+        }
+        write!(
+            f,
+            "[{}] account={} txn_id={}",
+            self.category, self.account, self.txn_id
+        )?;
+        if let Some(expected) = self.expected {
+            write!(f, " expected={}", expected)?;
+        }
+        if let Some(actual) = self.actual {
+            write!(f, " actual={}", actual)?;
+        }
+        if let Some(line) = self.summary_line {
+            write!(f, " summary_line={}", line)?;
+        }
+        if let Some(line) = self.txn_line {
+            write!(f, " txn_line={}", line)?;
+        }
+        if let Some(rate) = &self.rate {
+            write!(f, " rate={}", rate)?;
+        }
+        if let Some(days) = self.days_to_settle {
+            write!(f, " days_to_settle={}", days)?;
+        }
+        Ok(())
+    }
+}
+
+/// A summary row that was checked, whether or not anything was found.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SummaryRow {
+    pub id: String,
+    pub line: u64,
+}
+
+/// Every finding from a validation run, plus running totals.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Report {
+    /// Number of rows in the transaction summary
+    pub summaries: usize,
+    /// Every summary row checked, in file order. Only kept when
+    /// [`Options::record_rows`](super::options::Options::record_rows) is set
+    pub rows: Vec<SummaryRow>,
+    /// Number of distinct (account, txn_id) keys in the transactions ledger,
+    /// once duplicate rows are dropped
+    pub txns: usize,
+    /// Number of summary legs looked up in the ledger
+    pub legs: usize,
+    /// Number of summary legs that matched their ledger row exactly
+    pub matched: usize,
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    pub fn new(txns: usize) -> Self {
+        Report {
+            txns,
+            ..Default::default()
+        }
+    }
+
+    /// Keep a note of the summary row `id` on `line`.
+    pub fn row(&mut self, id: &str, line: u64) {
+        self.rows.push(SummaryRow {
+            id: id.to_owned(),
+            line,
+        });
+    }
+
+    /// Record the findings from looking up one summary leg. A leg with
+    /// none matched.
+    pub fn record(&mut self, findings: Vec<Finding>) {
+        self.legs += 1;
+        if findings.is_empty() {
+            self.matched += 1;
+        }
+        self.findings.extend(findings);
+    }
+
+    /// Record a finding that isn't tied to a summary leg.
+    pub fn push(&mut self, finding: Finding) {
+        self.findings.push(finding);
+    }
+
+    /// Fold another report's counts and findings into this one.
+    ///
+    /// The ledger row count is left alone, since both reports are
+    /// expected to describe the same ledger.
+    pub fn merge(&mut self, other: Report) {
+        self.summaries += other.summaries;
+        self.rows.extend(other.rows);
+        self.legs += other.legs;
+        self.matched += other.matched;
+        self.findings.extend(other.findings);
+    }
+
+    pub fn count(&self, category: Category) -> usize {
+        self.findings
+            .iter()
+            .filter(|finding| finding.category == category)
+            .count()
+    }
+
+    /// Number of findings that make the report invalid.
+    pub fn errors(&self) -> usize {
+        self.findings
+            .iter()
+            .filter(|finding| finding.severity == Severity::Error)
+            .count()
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.errors() == 0
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for finding in self.findings.iter() {
+            writeln!(f, "{}", finding)?;
+        }
+        writeln!(f, "summary rows: {}", self.summaries)?;
+        writeln!(f, "ledger keys: {}", self.txns)?;
+        writeln!(f, "legs checked: {}", self.legs)?;
+        writeln!(f, "matched: {}", self.matched)?;
+        for category in Category::ALL.iter() {
+            writeln!(f, "{}: {}", category, self.count(*category))?;
+        }
+        writeln!(f, "total findings: {}", self.findings.len())?;
+        write!(f, "warnings: {}", self.findings.len() - self.errors())
+    }
+}
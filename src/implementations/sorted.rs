@@ -0,0 +1,644 @@
+//! # Sorted Implementation
+//!
+//! When both inputs are already sorted by `txn_id`, there is no need to
+//! hold either of them in memory. This implementation streams the summary
+//! and the ledger in lockstep and merge-joins them one `txn_id` at a time,
+//! so that only the rows sharing the current `txn_id` are held at once.
+//!
+//! Ids are compared byte by byte, which is the order `LC_ALL=C sort` puts
+//! them in. The order is checked as the files are read: a row whose
+//! `txn_id` sorts before the row above it is reported as
+//! [`Category::OutOfOrder`] and otherwise left out, since its partners
+//! have already gone by.
+//!
+//! What is still held in memory is the findings and, when balances are
+//! checked, the last balance of each account.
+//!
+
+use csv::{StringRecord, StringRecordsIntoIter};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::PathBuf;
+
+use super::amount::{Money, Total};
+use super::columns::{Layout, TXN_COLUMNS};
+use super::error::{line_of, Error};
+use super::input::Input;
+use super::options::Options;
+use super::report::{Category, Finding, Report, Severity};
+use super::validator::Validator;
+
+/// A row of either input, as far as the merge is concerned.
+trait Row: Sized {
+    fn from_row(row: &StringRecord, layout: &Layout) -> Result<Self, Error>;
+
+    fn txn_id(&self) -> &str;
+
+    /// Describe the row as breaking the sort order.
+    fn out_of_order(&self) -> Finding;
+}
+
+/// A transfer row or, in a summary with one row per leg, a single leg.
+#[derive(Debug)]
+struct TxnSummary {
+    id: String,
+    /// The account of a leg row; empty for a transfer row
+    account: String,
+    amount: i64,
+    /// The account and signed amount of each leg, in the same order as the
+    /// other implementations check them
+    legs: Vec<(String, i64)>,
+    currency: String,
+    date: String,
+    line: u64,
+}
+
+impl Row for TxnSummary {
+    fn from_row(row: &StringRecord, layout: &Layout) -> Result<Self, Error> {
+        let id = layout.field(row, "id")?.to_owned();
+        let amount = layout.amount(row, "amount")?;
+        let (account, legs) = if layout.has("account") {
+            let account = layout.field(row, "account")?.to_owned();
+            (account.clone(), vec![(account, amount)])
+        } else {
+            let from = layout.field(row, "from")?.to_owned();
+            let to = layout.field(row, "to")?.to_owned();
+            let debit = layout.debit(row, "amount", amount)?;
+            (String::new(), vec![(from, debit), (to, amount)])
+        };
+        Ok(TxnSummary {
+            id,
+            account,
+            amount,
+            legs,
+            currency: layout.optional(row, "currency")?.to_owned(),
+            date: layout.date(row)?.to_owned(),
+            line: line_of(row),
+        })
+    }
+
+    fn txn_id(&self) -> &str {
+        &self.id
+    }
+
+    fn out_of_order(&self) -> Finding {
+        Finding {
+            category: Category::OutOfOrder,
+            severity: Severity::Error,
+            account: self.account.clone(),
+            txn_id: self.id.clone(),
+            expected: None,
+            actual: Some(self.amount),
+            summary_line: Some(self.line),
+            txn_line: None,
+            rate: None,
+            days_to_settle: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Transaction {
+    account: String,
+    txn_id: String,
+    amount: i64,
+    balance: i64,
+    currency: String,
+    date: String,
+    line: u64,
+}
+
+impl Row for Transaction {
+    fn from_row(row: &StringRecord, layout: &Layout) -> Result<Self, Error> {
+        Ok(Transaction {
+            account: layout.field(row, "account")?.to_owned(),
+            txn_id: layout.field(row, "txn_id")?.to_owned(),
+            amount: layout.amount(row, "amount")?,
+            balance: layout.amount(row, "balance")?,
+            currency: layout.optional(row, "currency")?.to_owned(),
+            date: layout.date(row)?.to_owned(),
+            line: line_of(row),
+        })
+    }
+
+    fn txn_id(&self) -> &str {
+        &self.txn_id
+    }
+
+    fn out_of_order(&self) -> Finding {
+        Finding {
+            category: Category::OutOfOrder,
+            severity: Severity::Error,
+            account: self.account.clone(),
+            txn_id: self.txn_id.clone(),
+            expected: None,
+            actual: Some(self.amount),
+            summary_line: None,
+            txn_line: Some(self.line),
+            rate: None,
+            days_to_settle: None,
+        }
+    }
+}
+
+/// One input, read a row ahead so that the merge can see which `txn_id`
+/// comes next.
+struct Stream<T> {
+    path: PathBuf,
+    records: StringRecordsIntoIter<Box<dyn Read>>,
+    layout: Layout,
+    next: Option<T>,
+    /// Rows that sorted before the row above them
+    out_of_order: Vec<Finding>,
+}
+
+impl<T: Row> Stream<T> {
+    /// Open `input`, whose fields are separated by `delimiter`, finding its
+    /// columns with `resolve`.
+    fn open<F>(input: Input, delimiter: u8, resolve: F) -> Result<Self, Error>
+    where
+        F: FnOnce(&StringRecord) -> Result<Layout, Error>,
+    {
+        let path = input.name().to_owned();
+        let mut reader = input.open_delimited(delimiter)?;
+        let headers = reader.headers().map_err(|e| Error::csv(&path, e))?;
+        let layout = resolve(headers).map_err(|e| e.in_file(&path))?;
+        let mut stream = Stream {
+            path,
+            records: reader.into_records(),
+            layout,
+            next: None,
+            out_of_order: Vec::new(),
+        };
+        stream.advance(None)?;
+        Ok(stream)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.next.as_ref().map(Row::txn_id)
+    }
+
+    /// Read up to the next row that doesn't sort before `last`.
+    fn advance(&mut self, last: Option<&str>) -> Result<(), Error> {
+        self.next = None;
+        let path = &self.path;
+        for record in &mut self.records {
+            let record = record.map_err(|e| Error::csv(path, e))?;
+            let row = T::from_row(&record, &self.layout).map_err(|e| e.in_file(path))?;
+            match last {
+                Some(last) if row.txn_id() < last => self.out_of_order.push(row.out_of_order()),
+                _ => {
+                    self.next = Some(row);
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Every row with this `txn_id`, which must be the next one up.
+    fn take(&mut self, txn_id: &str) -> Result<Vec<T>, Error> {
+        let mut group = Vec::new();
+        while self.peek() == Some(txn_id) {
+            group.extend(self.next.take());
+            self.advance(Some(txn_id))?;
+        }
+        Ok(group)
+    }
+}
+
+/// The state of a merge, built up one `txn_id` at a time.
+#[derive(Default)]
+struct Merge {
+    report: Report,
+    unbalanced: Vec<Finding>,
+    orphans: Vec<Finding>,
+    balance_breaks: Vec<Finding>,
+    summary_duplicates: Vec<Finding>,
+    txn_duplicates: Vec<Finding>,
+    /// The last balance seen per account, or `None` once it has broken.
+    /// Only kept when balances are checked
+    balances: HashMap<String, Option<i64>>,
+}
+
+impl Merge {
+    /// Join the summary rows and ledger rows that share a `txn_id`.
+    fn join(&mut self, summaries: &[TxnSummary], txns: &[Transaction], options: &Options) {
+        // Transfer rows all have an empty account, so repeating the id is
+        // enough to make one a duplicate
+        let mut rows_per_account: HashMap<&str, usize> = HashMap::new();
+        let mut total = Total::new(summaries.first().map_or("", |first| &first.currency));
+        for txn_summary in summaries.iter() {
+            *rows_per_account.entry(&txn_summary.account).or_default() += 1;
+            for &(_, amount) in txn_summary.legs.iter() {
+                total.add(Money {
+                    amount,
+                    currency: &txn_summary.currency,
+                });
+            }
+        }
+        self.summary_duplicates.extend(
+            summaries
+                .iter()
+                .filter(|txn_summary| rows_per_account[txn_summary.account.as_str()] > 1)
+                .map(summary_duplicate),
+        );
+        if let (Some(first), Some(sum)) = (summaries.first(), total.unbalanced()) {
+            self.unbalanced.push(Finding {
+                category: Category::Unbalanced,
+                severity: Severity::Error,
+                account: String::new(),
+                txn_id: first.id.clone(),
+                expected: Some(0),
+                actual: sum,
+                summary_line: Some(first.line),
+                txn_line: None,
+                rate: None,
+                days_to_settle: None,
+            });
+        }
+
+        // Only the first row for each account is joined, as the in-memory
+        // implementations only keep the first row for each key
+        let mut first: HashMap<&str, usize> = HashMap::new();
+        let mut kept = vec![true; txns.len()];
+        let mut reported = vec![false; txns.len()];
+        for (index, txn) in txns.iter().enumerate() {
+            match first.entry(&txn.account) {
+                Entry::Occupied(original) => {
+                    let original = *original.get();
+                    // The first occurrence is reported alongside the first repeat
+                    if !reported[original] {
+                        reported[original] = true;
+                        self.txn_duplicates.push(txn_duplicate(&txns[original]));
+                    }
+                    kept[index] = false;
+                    self.txn_duplicates.push(txn_duplicate(txn));
+                }
+                Entry::Vacant(slot) => {
+                    slot.insert(index);
+                }
+            }
+        }
+        self.report.txns += first.len();
+
+        let mut referenced = vec![false; txns.len()];
+        for txn_summary in summaries.iter() {
+            self.report.summaries += 1;
+            if options.record_rows {
+                self.report.row(&txn_summary.id, txn_summary.line);
+            }
+            for (account, amount) in txn_summary.legs.iter() {
+                let (account, amount) = (account.as_str(), *amount);
+                let txn = first.get(account).map(|&index| {
+                    referenced[index] = true;
+                    &txns[index]
+                });
+                let finding = |category| Finding {
+                    category,
+                    severity: Severity::Error,
+                    account: account.to_owned(),
+                    txn_id: txn_summary.id.clone(),
+                    expected: Some(amount),
+                    actual: txn.map(|txn| txn.amount),
+                    summary_line: Some(txn_summary.line),
+                    txn_line: txn.map(|txn| txn.line),
+                    rate: None,
+                    days_to_settle: None,
+                };
+                self.report.record(match txn {
+                    Some(txn) => options.check_posting(
+                        Money {
+                            amount,
+                            currency: &txn_summary.currency,
+                        },
+                        Money {
+                            amount: txn.amount,
+                            currency: &txn.currency,
+                        },
+                        &txn_summary.date,
+                        &txn.date,
+                        finding,
+                    ),
+                    None => vec![finding(Category::Missing)],
+                });
+            }
+        }
+
+        for (index, txn) in txns.iter().enumerate() {
+            if !kept[index] {
+                continue;
+            }
+            if !referenced[index] {
+                self.orphans.push(orphan_finding(txn));
+            }
+            if options.check_balances {
+                self.check_balance(txn);
+            }
+        }
+    }
+
+    fn check_balance(&mut self, txn: &Transaction) {
+        let previous = match self.balances.get_mut(&txn.account) {
+            Some(previous) => previous,
+            None => {
+                self.balances.insert(txn.account.clone(), Some(txn.balance));
+                return;
+            }
+        };
+        if let Some(balance) = *previous {
+            let expected = balance.checked_add(txn.amount);
+            if expected == Some(txn.balance) {
+                *previous = Some(txn.balance);
+            } else {
+                *previous = None;
+                self.balance_breaks.push(Finding {
+                    category: Category::BalanceBreak,
+                    severity: Severity::Error,
+                    account: txn.account.clone(),
+                    txn_id: txn.txn_id.clone(),
+                    expected,
+                    actual: Some(txn.balance),
+                    summary_line: None,
+                    txn_line: Some(txn.line),
+                    rate: None,
+                    days_to_settle: None,
+                });
+            }
+        }
+    }
+}
+
+fn summary_duplicate(txn_summary: &TxnSummary) -> Finding {
+    Finding {
+        category: Category::summary_repeat(&txn_summary.account),
+        severity: Severity::Error,
+        account: txn_summary.account.clone(),
+        txn_id: txn_summary.id.clone(),
+        expected: None,
+        actual: None,
+        summary_line: Some(txn_summary.line),
+        txn_line: None,
+        rate: None,
+        days_to_settle: None,
+    }
+}
+
+fn txn_duplicate(txn: &Transaction) -> Finding {
+    Finding {
+        category: Category::DuplicateTxn,
+        severity: Severity::Error,
+        account: txn.account.clone(),
+        txn_id: txn.txn_id.clone(),
+        expected: None,
+        actual: Some(txn.amount),
+        summary_line: None,
+        txn_line: Some(txn.line),
+        rate: None,
+        days_to_settle: None,
+    }
+}
+
+fn orphan_finding(txn: &Transaction) -> Finding {
+    Finding {
+        category: Category::Orphan,
+        severity: Severity::Error,
+        account: txn.account.clone(),
+        txn_id: txn.txn_id.clone(),
+        expected: None,
+        actual: Some(txn.amount),
+        summary_line: None,
+        txn_line: Some(txn.line),
+        rate: None,
+        days_to_settle: None,
+    }
+}
+
+/// Stop at the first problem, in the same order as the in-memory
+/// implementations would find it.
+///
+/// Orphans and balance breaks can only be ranked once both inputs have
+/// been read, so this takes as long as [`validate_all`].
+pub fn validate<S: Into<Input>, T: Into<Input>>(
+    summary: S,
+    ledger: T,
+    options: &Options,
+) -> Result<(), Error> {
+    let report = validate_all(summary, ledger, options)?;
+    match report
+        .findings
+        .into_iter()
+        .find(|finding| finding.severity == Severity::Error)
+    {
+        Some(finding) => Err(finding.into()),
+        None => Ok(()),
+    }
+}
+
+/// Merge-join both inputs and collect every finding.
+///
+/// Rows out of order are listed first, followed by the same findings as the
+/// in-memory implementations, in the same order.
+pub fn validate_all<S: Into<Input>, T: Into<Input>>(
+    summary: S,
+    ledger: T,
+    options: &Options,
+) -> Result<Report, Error> {
+    let delimiter = options.columns.summary.delimiter();
+    let mut summary: Stream<TxnSummary> = Stream::open(summary.into(), delimiter, |headers| {
+        options
+            .columns
+            .summary
+            .resolve_summary(headers)
+            .map(|layout| layout.with_scales(&options.scales))
+    })?;
+    let delimiter = options.columns.txns.delimiter();
+    let mut ledger: Stream<Transaction> = Stream::open(ledger.into(), delimiter, |headers| {
+        options
+            .columns
+            .txns
+            .resolve(headers, &TXN_COLUMNS)
+            .map(|layout| layout.with_scales(&options.scales))
+    })?;
+
+    let mut merge = Merge::default();
+    loop {
+        let txn_id = match (summary.peek(), ledger.peek()) {
+            (Some(a), Some(b)) => a.min(b),
+            (Some(a), None) => a,
+            (None, Some(b)) => b,
+            (None, None) => break,
+        }
+        .to_owned();
+        let summaries = summary.take(&txn_id)?;
+        let txns = ledger.take(&txn_id)?;
+        merge.join(&summaries, &txns, options);
+    }
+
+    let mut txn_duplicates = merge.txn_duplicates;
+    txn_duplicates.sort_by(|a, b| {
+        (&a.account, &a.txn_id, a.txn_line).cmp(&(&b.account, &b.txn_id, b.txn_line))
+    });
+    let duplicates = options.duplicates.apply(
+        merge
+            .summary_duplicates
+            .into_iter()
+            .chain(txn_duplicates)
+            .collect(),
+    )?;
+
+    let mut report = merge.report;
+    let mut out_of_order = summary.out_of_order;
+    out_of_order.extend(ledger.out_of_order);
+    let leg_findings = std::mem::replace(&mut report.findings, out_of_order);
+    report.findings.extend(leg_findings);
+    report.findings.extend(merge.unbalanced);
+    report.findings.extend(merge.orphans);
+    report.findings.extend(merge.balance_breaks);
+    report.findings.extend(duplicates);
+    Ok(report)
+}
+
+/// The sorted merge-join implementation as a [`Validator`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Sorted;
+
+impl Validator for Sorted {
+    fn name(&self) -> &'static str {
+        "sorted"
+    }
+
+    fn description(&self) -> &'static str {
+        "streams both inputs, which must be sorted by txn_id, and merge-joins them"
+    }
+
+    fn needs_sorted_input(&self) -> bool {
+        true
+    }
+
+    fn validate(&self, summary: Input, txns: Input, options: &Options) -> Result<(), Error> {
+        validate(summary, txns, options)
+    }
+
+    fn report(&self, summary: Input, txns: Input, options: &Options) -> Result<Report, Error> {
+        validate_all(summary, txns, options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::implementations::options::DuplicatePolicy;
+    use crate::implementations::owned;
+
+    /// Pairs of fixtures that are both sorted by `txn_id`.
+    const SORTED_PAIRS: [(&str, &str); 5] = [
+        ("resources/summary0.csv", "resources/txns0.csv"),
+        ("resources/summary0.csv", "resources/txns_orphan.csv"),
+        (
+            "resources/summary_balance.csv",
+            "resources/txns_balance.csv",
+        ),
+        ("resources/summary_legs.csv", "resources/txns_legs.csv"),
+        ("resources/summary_sorted.csv", "resources/txns_sorted.csv"),
+    ];
+
+    #[test]
+    fn validate_all_agrees_with_owned_on_sorted_inputs() {
+        for duplicates in [DuplicatePolicy::Fail, DuplicatePolicy::Warn].iter() {
+            let options = Options {
+                check_balances: true,
+                duplicates: *duplicates,
+                record_rows: true,
+                ..Default::default()
+            };
+            for (summary, txns) in SORTED_PAIRS.iter() {
+                let owned_report =
+                    owned::validate_all(*summary, *txns, &options).map_err(|e| e.to_string());
+                let sorted_report =
+                    validate_all(*summary, *txns, &options).map_err(|e| e.to_string());
+                assert_eq!(sorted_report, owned_report, "{} vs {}", summary, txns);
+            }
+        }
+    }
+
+    #[test]
+    fn rows_out_of_order_are_reported_and_left_out() {
+        let report = validate_all(
+            "resources/summary_bad.csv",
+            "resources/txns_bad.csv",
+            &Options::default(),
+        )
+        .unwrap();
+        // `9` sorts before `:b`, so the orphan on line 9 is out of order
+        assert_eq!(
+            report.findings[0],
+            Finding {
+                category: Category::OutOfOrder,
+                severity: Severity::Error,
+                account: "z".into(),
+                txn_id: "9".into(),
+                expected: None,
+                actual: Some(-10),
+                summary_line: None,
+                txn_line: Some(9),
+                rate: None,
+                days_to_settle: None,
+            }
+        );
+        assert_eq!(report.count(Category::OutOfOrder), 1);
+        assert_eq!(report.count(Category::Missing), 2);
+        assert_eq!(report.count(Category::AmountMismatch), 1);
+        assert_eq!(report.count(Category::Orphan), 1);
+        assert_eq!(report.summaries, 4);
+        assert_eq!(report.legs, 8);
+
+        let err = validate(
+            "resources/summary_dup.csv",
+            "resources/txns_dup.csv",
+            &Options::default(),
+        )
+        .unwrap_err()
+        .to_string();
+        assert_eq!(
+            err,
+            "Out of order: [out_of_order] account= txn_id=1 actual=100 summary_line=4"
+        );
+    }
+
+    #[test]
+    fn duplicates_within_a_txn_id_are_found() {
+        let summary = "id,from,to,amount\n1,a,b,100\n1,a,b,100\n2,q,r,200\n";
+        let txns = "account,txn_id,amount,balance\n\
+                    a,1,-100,900\nb,1,100,100\na,1,-100,800\na,1,-100,700\n\
+                    q,2,-200,0\nr,2,200,200\n";
+        let options = Options {
+            duplicates: DuplicatePolicy::Warn,
+            ..Default::default()
+        };
+        let report = validate_all(
+            Input::reader("summary", summary.as_bytes()),
+            Input::reader("txns", txns.as_bytes()),
+            &options,
+        )
+        .unwrap();
+        let lines = report
+            .findings
+            .iter()
+            .map(|finding| (finding.category, finding.summary_line.or(finding.txn_line)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                (Category::DuplicateSummaryId, Some(2)),
+                (Category::DuplicateSummaryId, Some(3)),
+                (Category::DuplicateTxn, Some(2)),
+                (Category::DuplicateTxn, Some(4)),
+                (Category::DuplicateTxn, Some(5)),
+            ]
+        );
+        assert!(report.is_valid());
+        assert_eq!(report.txns, 4);
+    }
+}
@@ -0,0 +1,819 @@
+//! # Spill Implementation
+//!
+//! The other implementations hold the whole ledger in memory, which stops
+//! working once a ledger is larger than RAM. This one streams each input
+//! once, partitioning its rows by account and txn_id into CSV spill files on
+//! disk, and then joins one partition at a time. A partition that would
+//! take more than the memory budget to load is split again, so memory use
+//! follows the budget rather than the size of the inputs.
+//!
+//! The findings are the same as the in-memory implementations', in the
+//! same order. What is still held in memory grows with the output and the
+//! number of accounts rather than the number of rows: the findings
+//! themselves, the lines of duplicate ledger rows, and the last balance of
+//! each account when balances are checked.
+//!
+
+use csv::{ReaderBuilder, StringRecord, Writer};
+use std::collections::hash_map::{DefaultHasher, Entry};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+use super::amount::{Money, Total};
+use super::columns::{Layout, TXN_COLUMNS};
+use super::error::{field, line_of, parse_field, Error};
+use super::input::Input;
+use super::options::Options;
+use super::report::{Category, Finding, Report, Severity};
+use super::validator::Validator;
+
+/// The memory budget used when none is given: 1 GiB.
+pub const DEFAULT_MEMORY_BUDGET: u64 = 1 << 30;
+
+/// Number of partitions a spill is split into at each level. Each holds an
+/// open writer while it is filled, and the summary fills its legs and ids
+/// at once, so at most twice this many files are open together. That stays
+/// well under the 256 descriptors some hosts allow a process by default;
+/// larger inputs are handled by splitting partitions again, not by a wider
+/// fan out.
+const FAN_OUT: usize = 16;
+/// How many times a partition may be split before it is loaded as is.
+/// Only a single key repeated on a great many rows should get this far.
+const MAX_DEPTH: u32 = 4;
+/// Rough ratio between the memory a spilled row takes once loaded into a
+/// hash map and the space it takes on disk.
+const EXPANSION: u64 = 4;
+
+/// A ledger row's account and txn_id, kept apart so that ids containing
+/// any separator can't run together.
+type Key = (String, String);
+
+#[derive(Debug)]
+struct Transaction {
+    account: String,
+    txn_id: String,
+    amount: i64,
+    balance: i64,
+    line: u64,
+    currency: String,
+    date: String,
+}
+
+impl Transaction {
+    fn from_row(row: &StringRecord, layout: &Layout) -> Result<Self, Error> {
+        Ok(Transaction {
+            account: layout.field(row, "account")?.to_owned(),
+            txn_id: layout.field(row, "txn_id")?.to_owned(),
+            amount: layout.amount(row, "amount")?,
+            balance: layout.amount(row, "balance")?,
+            line: line_of(row),
+            currency: layout.optional(row, "currency")?.to_owned(),
+            date: layout.date(row)?.to_owned(),
+        })
+    }
+
+    /// Read back a row written by [`Transaction::to_record`].
+    fn from_record(record: &StringRecord) -> Result<Self, Error> {
+        Ok(Transaction {
+            account: field(record, 0, "account")?.to_owned(),
+            txn_id: field(record, 1, "txn_id")?.to_owned(),
+            amount: parse_field(record, 2, "amount")?,
+            balance: parse_field(record, 3, "balance")?,
+            line: parse_field(record, 4, "line")?,
+            currency: field(record, 5, "currency")?.to_owned(),
+            date: field(record, 6, "date")?.to_owned(),
+        })
+    }
+
+    fn to_record(&self) -> [String; 7] {
+        [
+            self.account.clone(),
+            self.txn_id.clone(),
+            self.amount.to_string(),
+            self.balance.to_string(),
+            self.line.to_string(),
+            self.currency.clone(),
+            self.date.clone(),
+        ]
+    }
+
+    fn key(&self) -> Key {
+        (self.account.clone(), self.txn_id.clone())
+    }
+}
+
+/// One leg of a summary row, as spilled to disk.
+#[derive(Debug)]
+struct Leg {
+    account: String,
+    txn_id: String,
+    amount: i64,
+    summary_line: u64,
+    /// Position of the leg within its summary row
+    index: u8,
+    currency: String,
+    /// The summary row's date, which picks the rate to convert the leg at
+    /// and starts its settlement window
+    date: String,
+}
+
+impl Leg {
+    fn from_record(record: &StringRecord) -> Result<Self, Error> {
+        Ok(Leg {
+            account: field(record, 0, "account")?.to_owned(),
+            txn_id: field(record, 1, "txn_id")?.to_owned(),
+            amount: parse_field(record, 2, "amount")?,
+            summary_line: parse_field(record, 3, "summary_line")?,
+            index: parse_field(record, 4, "index")?,
+            currency: field(record, 5, "currency")?.to_owned(),
+            date: field(record, 6, "date")?.to_owned(),
+        })
+    }
+
+    fn key(&self) -> Key {
+        (self.account.clone(), self.txn_id.clone())
+    }
+
+    /// Compare the leg against the ledger, describing any discrepancy.
+    fn check(&self, txns: &HashMap<Key, Transaction>, options: &Options) -> Vec<Finding> {
+        let finding = |category, txn: Option<&Transaction>| Finding {
+            category,
+            severity: Severity::Error,
+            account: self.account.clone(),
+            txn_id: self.txn_id.clone(),
+            expected: Some(self.amount),
+            actual: txn.map(|txn| txn.amount),
+            summary_line: Some(self.summary_line),
+            txn_line: txn.map(|txn| txn.line),
+            rate: None,
+            days_to_settle: None,
+        };
+        match txns.get(&self.key()) {
+            Some(txn) => options.check_posting(
+                Money {
+                    amount: self.amount,
+                    currency: &self.currency,
+                },
+                Money {
+                    amount: txn.amount,
+                    currency: &txn.currency,
+                },
+                &self.date,
+                &txn.date,
+                |category| finding(category, Some(txn)),
+            ),
+            None => vec![finding(Category::Missing, None)],
+        }
+    }
+}
+
+/// A spill split into [`FAN_OUT`] files by the hash of each row's key.
+struct Partitions {
+    depth: u32,
+    paths: Vec<PathBuf>,
+    writers: Vec<Writer<File>>,
+}
+
+impl Partitions {
+    /// Create the files `{prefix}.0.csv` and so on, for rows partitioned at
+    /// `depth`.
+    fn create(prefix: &Path, depth: u32) -> Result<Self, Error> {
+        let mut paths = Vec::with_capacity(FAN_OUT);
+        let mut writers = Vec::with_capacity(FAN_OUT);
+        for i in 0..FAN_OUT {
+            let path = PathBuf::from(format!("{}.{}.csv", prefix.display(), i));
+            writers.push(Writer::from_path(&path).map_err(|e| Error::csv(&path, e))?);
+            paths.push(path);
+        }
+        Ok(Partitions {
+            depth,
+            paths,
+            writers,
+        })
+    }
+
+    fn write<K, I, F>(&mut self, key: &K, record: I) -> Result<(), Error>
+    where
+        K: Hash + ?Sized,
+        I: IntoIterator<Item = F>,
+        F: AsRef<[u8]>,
+    {
+        let i = partition(key, self.depth);
+        self.writers[i]
+            .write_record(record)
+            .map_err(|e| Error::csv(&self.paths[i], e))
+    }
+
+    fn finish(self) -> Result<Vec<PathBuf>, Error> {
+        for (mut writer, path) in self.writers.into_iter().zip(self.paths.iter()) {
+            writer.flush().map_err(|e| Error::csv(path, e.into()))?;
+        }
+        Ok(self.paths)
+    }
+}
+
+/// Which of [`FAN_OUT`] partitions `key` belongs to at `depth`. Each depth
+/// hashes differently, so that splitting a partition spreads its rows out.
+fn partition<K: Hash + ?Sized>(key: &K, depth: u32) -> usize {
+    let mut hasher = DefaultHasher::new();
+    depth.hash(&mut hasher);
+    key.hash(&mut hasher);
+    (hasher.finish() % FAN_OUT as u64) as usize
+}
+
+/// The key ledger and leg spills are partitioned on.
+fn ledger_key(record: &StringRecord) -> Key {
+    (
+        record.get(0).unwrap_or_default().to_owned(),
+        record.get(1).unwrap_or_default().to_owned(),
+    )
+}
+
+/// The key summary id spills are partitioned on.
+fn id_key(record: &StringRecord) -> String {
+    record.get(0).unwrap_or_default().to_owned()
+}
+
+fn records(path: &Path) -> Result<impl Iterator<Item = Result<StringRecord, Error>>, Error> {
+    let reader = ReaderBuilder::new()
+        .has_headers(false)
+        .from_path(path)
+        .map_err(|e| Error::csv(path, e))?;
+    let path = path.to_owned();
+    Ok(reader
+        .into_records()
+        .map(move |record| record.map_err(|e| Error::csv(&path, e))))
+}
+
+/// Whether loading every one of `paths` at once would go over `budget`.
+fn too_big(paths: &[&Path], budget: u64) -> Result<bool, Error> {
+    let mut size = 0;
+    for path in paths {
+        size += fs::metadata(path)
+            .map_err(|e| Error::csv(path, e.into()))?
+            .len();
+    }
+    Ok(size.saturating_mul(EXPANSION) > budget)
+}
+
+/// Split a spill file into [`FAN_OUT`] smaller ones at the next depth,
+/// keeping rows in order, and remove it.
+fn split<K: Hash>(
+    path: &Path,
+    depth: u32,
+    key: fn(&StringRecord) -> K,
+) -> Result<Vec<PathBuf>, Error> {
+    let mut parts = Partitions::create(&path.with_extension(""), depth + 1)?;
+    for record in records(path)? {
+        let record = record?;
+        parts.write(&key(&record), &record)?;
+    }
+    fs::remove_file(path).map_err(|e| Error::csv(path, e.into()))?;
+    parts.finish()
+}
+
+fn remove(path: &Path) -> Result<(), Error> {
+    fs::remove_file(path).map_err(|e| Error::csv(path, e.into()))
+}
+
+/// A summary row's legs, each an account and a signed amount.
+struct SummaryLegs {
+    id: String,
+    /// The account of a leg row; empty for a transfer row
+    account: String,
+    legs: Vec<(String, i64)>,
+    currency: String,
+    date: String,
+}
+
+fn parse_summary_row(row: &StringRecord, layout: &Layout) -> Result<SummaryLegs, Error> {
+    let id = layout.field(row, "id")?.to_owned();
+    let amount = layout.amount(row, "amount")?;
+    let currency = layout.optional(row, "currency")?.to_owned();
+    let date = layout.date(row)?.to_owned();
+    if layout.has("account") {
+        let account = layout.field(row, "account")?.to_owned();
+        return Ok(SummaryLegs {
+            id,
+            legs: vec![(account.clone(), amount)],
+            account,
+            currency,
+            date,
+        });
+    }
+    let from = layout.field(row, "from")?.to_owned();
+    let to = layout.field(row, "to")?.to_owned();
+    let debit = layout.debit(row, "amount", amount)?;
+    Ok(SummaryLegs {
+        id,
+        account: String::new(),
+        legs: vec![(from, debit), (to, amount)],
+        currency,
+        date,
+    })
+}
+
+/// Stream the summary into spills of legs and of ids, counting its rows
+/// in `report`.
+fn spill_summary(
+    input: Input,
+    dir: &Path,
+    options: &Options,
+    report: &mut Report,
+) -> Result<(Vec<PathBuf>, Vec<PathBuf>), Error> {
+    let path = input.name().to_owned();
+    let mut reader = input.open_delimited(options.columns.summary.delimiter())?;
+    let headers = reader.headers().map_err(|e| Error::csv(&path, e))?;
+    let layout = options
+        .columns
+        .summary
+        .resolve_summary(headers)
+        .map_err(|e| e.in_file(&path))?
+        .with_scales(&options.scales);
+    let mut legs = Partitions::create(&dir.join("legs"), 0)?;
+    let mut ids = Partitions::create(&dir.join("ids"), 0)?;
+    for row in reader.records() {
+        let row = row.map_err(|e| Error::csv(&path, e))?;
+        let summary_legs = parse_summary_row(&row, &layout).map_err(|e| e.in_file(&path))?;
+        let id = &summary_legs.id;
+        let line = line_of(&row).to_string();
+        report.summaries += 1;
+        if options.record_rows {
+            report.row(id, line_of(&row));
+        }
+        let (currency, date) = (&summary_legs.currency, &summary_legs.date);
+        for (index, (account, amount)) in summary_legs.legs.iter().enumerate() {
+            legs.write(
+                &(account, id),
+                [
+                    account,
+                    id,
+                    &amount.to_string(),
+                    &line,
+                    &index.to_string(),
+                    currency,
+                    date,
+                ],
+            )?;
+        }
+        // A row is one leg, or a debit and credit of the same amount, so
+        // what it adds to its transaction's total can't overflow
+        let net: i64 = summary_legs.legs.iter().map(|(_, amount)| amount).sum();
+        ids.write(
+            id,
+            [id, &line, &summary_legs.account, &net.to_string(), currency],
+        )?;
+    }
+    Ok((legs.finish()?, ids.finish()?))
+}
+
+/// Stream the ledger into a partitioned spill, and also into one spill in
+/// file order if balances are to be checked.
+fn spill_ledger(
+    input: Input,
+    dir: &Path,
+    options: &Options,
+) -> Result<(Vec<PathBuf>, Option<PathBuf>), Error> {
+    let path = input.name().to_owned();
+    let mut reader = input.open_delimited(options.columns.txns.delimiter())?;
+    let headers = reader.headers().map_err(|e| Error::csv(&path, e))?;
+    let layout = options
+        .columns
+        .txns
+        .resolve(headers, &TXN_COLUMNS)
+        .map_err(|e| e.in_file(&path))?
+        .with_scales(&options.scales);
+    let mut txns = Partitions::create(&dir.join("txns"), 0)?;
+    let ordered_path = dir.join("ledger.csv");
+    let mut ordered = match options.check_balances {
+        true => Some(Writer::from_path(&ordered_path).map_err(|e| Error::csv(&ordered_path, e))?),
+        false => None,
+    };
+    for row in reader.records() {
+        let row = row.map_err(|e| Error::csv(&path, e))?;
+        let txn = Transaction::from_row(&row, &layout).map_err(|e| e.in_file(&path))?;
+        let record = txn.to_record();
+        txns.write(&txn.key(), &record)?;
+        if let Some(ordered) = ordered.as_mut() {
+            ordered
+                .write_record(&record)
+                .map_err(|e| Error::csv(&ordered_path, e))?;
+        }
+    }
+    match ordered {
+        Some(mut ordered) => {
+            ordered
+                .flush()
+                .map_err(|e| Error::csv(&ordered_path, e.into()))?;
+            Ok((txns.finish()?, Some(ordered_path)))
+        }
+        None => Ok((txns.finish()?, None)),
+    }
+}
+
+/// Everything found while joining partitions, gathered up so it can be
+/// put back in file order at the end.
+#[derive(Default)]
+struct Joined {
+    budget: u64,
+    txns: usize,
+    legs: usize,
+    matched: usize,
+    leg_findings: Vec<(u64, u8, Finding)>,
+    unbalanced: Vec<Finding>,
+    orphans: Vec<Finding>,
+    summary_duplicates: Vec<Finding>,
+    txn_duplicates: Vec<Finding>,
+    /// Lines of ledger rows that repeat an earlier key, which the
+    /// in-memory implementations drop
+    duplicate_lines: HashSet<u64>,
+}
+
+impl Joined {
+    /// Join one partition of legs against the same partition of the
+    /// ledger, splitting both first if they're too big to load.
+    fn join(
+        &mut self,
+        legs: &Path,
+        txns: &Path,
+        depth: u32,
+        options: &Options,
+    ) -> Result<(), Error> {
+        if depth < MAX_DEPTH && too_big(&[legs, txns], self.budget)? {
+            let legs = split(legs, depth, ledger_key)?;
+            let txns = split(txns, depth, ledger_key)?;
+            for (legs, txns) in legs.iter().zip(txns.iter()) {
+                self.join(legs, txns, depth + 1, options)?;
+            }
+            return Ok(());
+        }
+
+        let mut ledger: HashMap<Key, Transaction> = HashMap::new();
+        let mut reported: HashSet<Key> = HashSet::new();
+        for record in records(txns)? {
+            let txn = Transaction::from_record(&record?).map_err(|e| e.in_file(txns))?;
+            match ledger.entry(txn.key()) {
+                Entry::Occupied(first) => {
+                    if reported.insert(first.key().clone()) {
+                        self.txn_duplicates.push(txn_duplicate(first.get()));
+                    }
+                    self.duplicate_lines.insert(txn.line);
+                    self.txn_duplicates.push(txn_duplicate(&txn));
+                }
+                Entry::Vacant(slot) => {
+                    slot.insert(txn);
+                }
+            }
+        }
+
+        let mut referenced: HashSet<Key> = HashSet::new();
+        for record in records(legs)? {
+            let leg = Leg::from_record(&record?).map_err(|e| e.in_file(legs))?;
+            self.legs += 1;
+            let findings = leg.check(&ledger, options);
+            if findings.is_empty() {
+                self.matched += 1;
+            }
+            for finding in findings {
+                self.leg_findings
+                    .push((leg.summary_line, leg.index, finding));
+            }
+            referenced.insert(leg.key());
+        }
+        for (key, txn) in ledger.iter() {
+            if !referenced.contains(key) {
+                self.orphans.push(orphan_finding(txn));
+            }
+        }
+        self.txns += ledger.len();
+        remove(legs)?;
+        remove(txns)
+    }
+
+    /// Find summary rows that repeat an earlier id, or id and account,
+    /// and transactions whose legs don't sum to zero, in one partition of
+    /// ids.
+    fn check_ids(&mut self, ids: &Path, depth: u32) -> Result<(), Error> {
+        if depth < MAX_DEPTH && too_big(&[ids], self.budget)? {
+            for part in split(ids, depth, id_key)? {
+                self.check_ids(&part, depth + 1)?;
+            }
+            return Ok(());
+        }
+        let mut lines_by_key: HashMap<(String, String), Vec<u64>> = HashMap::new();
+        let mut totals: HashMap<String, (u64, Total)> = HashMap::new();
+        for record in records(ids)? {
+            let record = record?;
+            let id = field(&record, 0, "id").map_err(|e| e.in_file(ids))?;
+            let line = parse_field(&record, 1, "line").map_err(|e| e.in_file(ids))?;
+            let account = field(&record, 2, "account").map_err(|e| e.in_file(ids))?;
+            let net = parse_field(&record, 3, "net").map_err(|e| e.in_file(ids))?;
+            let currency = field(&record, 4, "currency").map_err(|e| e.in_file(ids))?;
+            lines_by_key
+                .entry((id.to_owned(), account.to_owned()))
+                .or_default()
+                .push(line);
+            let (_, total) = totals
+                .entry(id.to_owned())
+                .or_insert_with(|| (line, Total::new(currency)));
+            total.add(Money {
+                amount: net,
+                currency,
+            });
+        }
+        for ((id, account), lines) in lines_by_key {
+            if lines.len() > 1 {
+                self.summary_duplicates.extend(
+                    lines
+                        .into_iter()
+                        .map(|line| summary_duplicate(&id, &account, line)),
+                );
+            }
+        }
+        for (id, (line, total)) in totals {
+            if let Some(sum) = total.unbalanced() {
+                self.unbalanced.push(Finding {
+                    category: Category::Unbalanced,
+                    severity: Severity::Error,
+                    account: String::new(),
+                    txn_id: id,
+                    expected: Some(0),
+                    actual: sum,
+                    summary_line: Some(line),
+                    txn_line: None,
+                    rate: None,
+                    days_to_settle: None,
+                });
+            }
+        }
+        remove(ids)
+    }
+}
+
+/// The first posting in each account whose balance doesn't follow from
+/// the one before it, read from the ledger spill in file order.
+///
+/// Rows on `skipped` lines are left out, as the in-memory implementations
+/// only keep the first row for each key.
+fn check_balances(ordered: &Path, skipped: &HashSet<u64>) -> Result<Vec<Finding>, Error> {
+    // The last balance seen per account, or `None` once it has broken
+    let mut balances: HashMap<String, Option<i64>> = HashMap::new();
+    let mut findings = Vec::new();
+    for record in records(ordered)? {
+        let txn = Transaction::from_record(&record?).map_err(|e| e.in_file(ordered))?;
+        if skipped.contains(&txn.line) {
+            continue;
+        }
+        let previous = match balances.get_mut(&txn.account) {
+            Some(previous) => previous,
+            None => {
+                balances.insert(txn.account, Some(txn.balance));
+                continue;
+            }
+        };
+        if let Some(balance) = *previous {
+            let expected = balance.checked_add(txn.amount);
+            if expected == Some(txn.balance) {
+                *previous = Some(txn.balance);
+            } else {
+                *previous = None;
+                findings.push(Finding {
+                    category: Category::BalanceBreak,
+                    severity: Severity::Error,
+                    account: txn.account,
+                    txn_id: txn.txn_id,
+                    expected,
+                    actual: Some(txn.balance),
+                    summary_line: None,
+                    txn_line: Some(txn.line),
+                    rate: None,
+                    days_to_settle: None,
+                });
+            }
+        }
+    }
+    remove(ordered)?;
+    Ok(findings)
+}
+
+fn summary_duplicate(id: &str, account: &str, line: u64) -> Finding {
+    Finding {
+        category: Category::summary_repeat(account),
+        severity: Severity::Error,
+        account: account.to_owned(),
+        txn_id: id.to_owned(),
+        expected: None,
+        actual: None,
+        summary_line: Some(line),
+        txn_line: None,
+        rate: None,
+        days_to_settle: None,
+    }
+}
+
+fn txn_duplicate(txn: &Transaction) -> Finding {
+    Finding {
+        category: Category::DuplicateTxn,
+        severity: Severity::Error,
+        account: txn.account.clone(),
+        txn_id: txn.txn_id.clone(),
+        expected: None,
+        actual: Some(txn.amount),
+        summary_line: None,
+        txn_line: Some(txn.line),
+        rate: None,
+        days_to_settle: None,
+    }
+}
+
+fn orphan_finding(txn: &Transaction) -> Finding {
+    Finding {
+        category: Category::Orphan,
+        severity: Severity::Error,
+        account: txn.account.clone(),
+        txn_id: txn.txn_id.clone(),
+        expected: None,
+        actual: Some(txn.amount),
+        summary_line: None,
+        txn_line: Some(txn.line),
+        rate: None,
+        days_to_settle: None,
+    }
+}
+
+/// Stop at the first problem, in the same order as the in-memory
+/// implementations would find it.
+///
+/// Every partition has to be joined before the earliest finding is known,
+/// so this takes as long as [`validate_all`].
+pub fn validate<S: Into<Input>, T: Into<Input>>(
+    summary: S,
+    ledger: T,
+    options: &Options,
+) -> Result<(), Error> {
+    let report = validate_all(summary, ledger, options)?;
+    match report
+        .findings
+        .into_iter()
+        .find(|finding| finding.severity == Severity::Error)
+    {
+        Some(finding) => Err(finding.into()),
+        None => Ok(()),
+    }
+}
+
+/// Walk both inputs through spill files and collect every finding.
+pub fn validate_all<S: Into<Input>, T: Into<Input>>(
+    summary: S,
+    ledger: T,
+    options: &Options,
+) -> Result<Report, Error> {
+    let spill_dir = options.spill_dir.clone().unwrap_or_else(env::temp_dir);
+    let dir = tempfile::Builder::new()
+        .prefix("rust_intro-spill-")
+        .tempdir_in(&spill_dir)
+        .map_err(|e| Error::csv(&spill_dir, e.into()))?;
+
+    let mut report = Report::default();
+    let (legs, ids) = spill_summary(summary.into(), dir.path(), options, &mut report)?;
+    let (txns, ordered) = spill_ledger(ledger.into(), dir.path(), options)?;
+
+    let mut joined = Joined {
+        budget: options.memory_budget.unwrap_or(DEFAULT_MEMORY_BUDGET),
+        ..Default::default()
+    };
+    for ids in ids.iter() {
+        joined.check_ids(ids, 0)?;
+    }
+    for (legs, txns) in legs.iter().zip(txns.iter()) {
+        joined.join(legs, txns, 0, options)?;
+    }
+
+    let mut summary_duplicates = joined.summary_duplicates;
+    summary_duplicates
+        .sort_by(|a, b| (&a.txn_id, a.summary_line).cmp(&(&b.txn_id, b.summary_line)));
+    let mut txn_duplicates = joined.txn_duplicates;
+    txn_duplicates.sort_by(|a, b| {
+        (&a.account, &a.txn_id, a.txn_line).cmp(&(&b.account, &b.txn_id, b.txn_line))
+    });
+    let duplicates = options.duplicates.apply(
+        summary_duplicates
+            .into_iter()
+            .chain(txn_duplicates)
+            .collect(),
+    )?;
+
+    report.txns = joined.txns;
+    report.legs = joined.legs;
+    report.matched = joined.matched;
+    let mut leg_findings = joined.leg_findings;
+    leg_findings.sort_by_key(|(line, index, _)| (*line, *index));
+    report
+        .findings
+        .extend(leg_findings.into_iter().map(|(_, _, finding)| finding));
+    let mut unbalanced = joined.unbalanced;
+    unbalanced.sort_by_key(|finding| finding.summary_line);
+    report.findings.extend(unbalanced);
+    let mut orphans = joined.orphans;
+    orphans.sort_by_key(|finding| finding.txn_line);
+    report.findings.extend(orphans);
+    if let Some(ordered) = ordered {
+        report
+            .findings
+            .extend(check_balances(&ordered, &joined.duplicate_lines)?);
+    }
+    report.findings.extend(duplicates);
+    Ok(report)
+}
+
+/// The spill implementation as a [`Validator`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Spill;
+
+impl Validator for Spill {
+    fn name(&self) -> &'static str {
+        "spill"
+    }
+
+    fn description(&self) -> &'static str {
+        "partitions both inputs on disk to stay within a memory budget"
+    }
+
+    fn validate(&self, summary: Input, txns: Input, options: &Options) -> Result<(), Error> {
+        validate(summary, txns, options)
+    }
+
+    fn report(&self, summary: Input, txns: Input, options: &Options) -> Result<Report, Error> {
+        validate_all(summary, txns, options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::implementations::options::DuplicatePolicy;
+    use crate::implementations::{owned, resource_pairs};
+
+    #[test]
+    fn validate_all_agrees_with_owned() {
+        for duplicates in [DuplicatePolicy::Fail, DuplicatePolicy::Warn].iter() {
+            let options = Options {
+                check_balances: true,
+                duplicates: *duplicates,
+                record_rows: true,
+                ..Default::default()
+            };
+            for (summary, txns) in resource_pairs() {
+                let owned_report =
+                    owned::validate_all(&summary, &txns, &options).map_err(|e| e.to_string());
+                let spill_report =
+                    validate_all(&summary, &txns, &options).map_err(|e| e.to_string());
+                assert_eq!(spill_report, owned_report, "{} vs {}", summary, txns);
+            }
+        }
+    }
+
+    #[test]
+    fn partitions_over_budget_are_split() {
+        let options = Options {
+            check_balances: true,
+            memory_budget: Some(4096),
+            ..Default::default()
+        };
+        let summary = "resources/summary1000.csv";
+        let txns = "resources/txns1000.csv";
+        assert_eq!(
+            validate_all(summary, txns, &options).unwrap(),
+            owned::validate_all(summary, txns, &options).unwrap()
+        );
+        let summary = "resources/summary_bad.csv";
+        let txns = "resources/txns_bad.csv";
+        assert_eq!(
+            validate(summary, txns, &options).map_err(|e| e.to_string()),
+            owned::validate(summary, txns, &options)
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        );
+    }
+
+    #[test]
+    fn spill_files_are_cleaned_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options {
+            check_balances: true,
+            memory_budget: Some(4096),
+            spill_dir: Some(dir.path().to_owned()),
+            ..Default::default()
+        };
+        validate_all(
+            "resources/summary1000.csv",
+            "resources/txns1000.csv",
+            &options,
+        )
+        .unwrap();
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,502 @@
+//! # Reconciliation Store
+//!
+//! A run's inputs and findings can also be loaded into a local SQLite
+//! file, so that they can be looked into afterwards with the saved
+//! reports in [`REPORTS`], or with any other SQL. Each load replaces
+//! whatever the store held before, in a single transaction, so the store
+//! always describes exactly one run.
+//!
+//! The store has three tables. Amounts are in minor units, as everywhere
+//! else, and line numbers count the header:
+//!
+//! ```sql
+//! -- One row per summary leg, so a transfer row gives a debit and a credit
+//! CREATE TABLE summary (
+//!     txn_id   TEXT    NOT NULL,
+//!     account  TEXT    NOT NULL,
+//!     amount   INTEGER NOT NULL,  -- signed, negative for the debit leg
+//!     currency TEXT    NOT NULL,  -- empty if the summary has no currency
+//!     date     TEXT    NOT NULL,  -- YYYY-MM-DD, or empty
+//!     line     INTEGER NOT NULL
+//! );
+//! -- One row per account and txn_id; only the first of any duplicates
+//! CREATE TABLE ledger (
+//!     account  TEXT    NOT NULL,
+//!     txn_id   TEXT    NOT NULL,
+//!     amount   INTEGER NOT NULL,
+//!     balance  INTEGER NOT NULL,
+//!     currency TEXT    NOT NULL,
+//!     date     TEXT    NOT NULL,
+//!     line     INTEGER NOT NULL
+//! );
+//! -- One row per finding, with NULL for values that don't apply
+//! CREATE TABLE findings (
+//!     category       TEXT    NOT NULL,  -- as in `Category::name`
+//!     severity       TEXT    NOT NULL,  -- `error` or `warning`
+//!     account        TEXT    NOT NULL,
+//!     txn_id         TEXT    NOT NULL,
+//!     expected       INTEGER,
+//!     actual         INTEGER,
+//!     summary_line   INTEGER,
+//!     txn_line       INTEGER,
+//!     rate           TEXT,              -- e.g. `EUR/USD@1.1 on 2024-01-02`
+//!     days_to_settle INTEGER,
+//!     date           TEXT               -- the summary leg's date, see below
+//! );
+//! ```
+//!
+//! A finding's `date` is that of the summary row it's about, so findings
+//! can be sliced by day without joining back to `summary`. Findings about
+//! a ledger row alone, such as orphans, take the row's posting date
+//! instead. It's NULL when the input has no date for it.
+//!
+//! Both inputs are read with the owned implementation's loaders, so a
+//! store can be loaded from whatever the other implementations accept.
+//!
+
+use rusqlite::types::ValueRef;
+use rusqlite::{params, Connection, OpenFlags};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use super::error::Error;
+use super::input::Input;
+use super::options::Options;
+use super::owned::{self, Ledger, Summary};
+use super::report::{Finding, Report};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS summary (
+        txn_id TEXT NOT NULL,
+        account TEXT NOT NULL,
+        amount INTEGER NOT NULL,
+        currency TEXT NOT NULL,
+        date TEXT NOT NULL,
+        line INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS summary_key ON summary (account, txn_id);
+    CREATE TABLE IF NOT EXISTS ledger (
+        account TEXT NOT NULL,
+        txn_id TEXT NOT NULL,
+        amount INTEGER NOT NULL,
+        balance INTEGER NOT NULL,
+        currency TEXT NOT NULL,
+        date TEXT NOT NULL,
+        line INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS ledger_key ON ledger (account, txn_id);
+    CREATE TABLE IF NOT EXISTS findings (
+        category TEXT NOT NULL,
+        severity TEXT NOT NULL,
+        account TEXT NOT NULL,
+        txn_id TEXT NOT NULL,
+        expected INTEGER,
+        actual INTEGER,
+        summary_line INTEGER,
+        txn_line INTEGER,
+        rate TEXT,
+        days_to_settle INTEGER,
+        date TEXT
+    );
+    CREATE INDEX IF NOT EXISTS findings_category ON findings (category);
+";
+
+/// A named query that can be run against a store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SavedReport {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub sql: &'static str,
+}
+
+/// The reports [`Store::query`] can run.
+pub const REPORTS: [SavedReport; 5] = [
+    SavedReport {
+        name: "top_mismatched_accounts",
+        description: "Accounts with the most amount mismatches, and by how much they're off",
+        sql: "SELECT account,
+                     COUNT(*) AS mismatches,
+                     SUM(ABS(actual - expected)) AS difference
+              FROM findings
+              WHERE category = 'amount_mismatch'
+              GROUP BY account
+              ORDER BY mismatches DESC, difference DESC, account
+              LIMIT 10",
+    },
+    SavedReport {
+        name: "unmatched_totals",
+        description: "Per currency, the summary legs and ledger rows nothing matched",
+        sql: "SELECT 'summary' AS side,
+                     s.currency AS currency,
+                     COUNT(*) AS rows,
+                     SUM(s.amount) AS total
+              FROM summary s
+              LEFT JOIN ledger l ON l.account = s.account AND l.txn_id = s.txn_id
+              WHERE l.line IS NULL
+              GROUP BY s.currency
+              UNION ALL
+              SELECT 'ledger', l.currency, COUNT(*), SUM(l.amount)
+              FROM ledger l
+              LEFT JOIN summary s ON s.account = l.account AND s.txn_id = l.txn_id
+              WHERE s.line IS NULL
+              GROUP BY l.currency
+              ORDER BY side DESC, currency",
+    },
+    SavedReport {
+        name: "findings_by_category",
+        description: "How many findings of each category and severity there are",
+        sql: "SELECT category, severity, COUNT(*) AS findings
+              FROM findings
+              GROUP BY category, severity
+              ORDER BY findings DESC, category, severity",
+    },
+    SavedReport {
+        name: "findings_by_day",
+        description: "How many findings of each category there are for each summary date",
+        sql: "SELECT date, category, COUNT(*) AS findings
+              FROM findings
+              GROUP BY date, category
+              ORDER BY date, category",
+    },
+    SavedReport {
+        name: "late_settlements",
+        description: "Ledger rows that posted after their settlement window, latest first",
+        sql: "SELECT account, txn_id, days_to_settle, summary_line, txn_line
+              FROM findings
+              WHERE category = 'late_settlement'
+              ORDER BY days_to_settle DESC, account, txn_id",
+    },
+];
+
+/// The saved report called `name`.
+pub fn saved_report(name: &str) -> Option<&'static SavedReport> {
+    REPORTS.iter().find(|report| report.name == name)
+}
+
+/// The rows a query returned, with every value written out as text and
+/// NULLs left empty.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+/// Add the `date` column to the findings of a store written before it
+/// existed.
+fn add_finding_dates(conn: &Connection) -> rusqlite::Result<()> {
+    let has_dates = conn
+        .prepare("SELECT 1 FROM pragma_table_info('findings') WHERE name = 'date'")?
+        .exists([])?;
+    if !has_dates {
+        conn.execute_batch("ALTER TABLE findings ADD COLUMN date TEXT")?;
+    }
+    Ok(())
+}
+
+/// A SQLite file holding a run, as described in the module docs.
+pub struct Store {
+    path: PathBuf,
+    conn: Connection,
+}
+
+impl Store {
+    /// Open the store at `path`, creating it and its tables if need be.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref().to_owned();
+        let conn = Connection::open(&path).map_err(|e| Error::store(&path, e))?;
+        conn.execute_batch(SCHEMA)
+            .and_then(|_| add_finding_dates(&conn))
+            .map_err(|e| Error::store(&path, e))?;
+        Ok(Store { path, conn })
+    }
+
+    /// Open an existing store at `path` to query it, without writing to it.
+    /// Fails if there's no store there.
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref().to_owned();
+        let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+        let conn = Connection::open_with_flags(&path, flags).map_err(|e| Error::store(&path, e))?;
+        Ok(Store { path, conn })
+    }
+
+    /// Reconcile `summary` against `txns` as [`owned::validate_all`] does,
+    /// and replace the store's contents with both inputs and the findings.
+    ///
+    /// The store is loaded even when the run fails on duplicate keys, with
+    /// the duplicates as its findings; only inputs that can't be read at
+    /// all leave it untouched.
+    pub fn load<S: Into<Input>, T: Into<Input>>(
+        &mut self,
+        summary: S,
+        txns: T,
+        options: &Options,
+    ) -> Result<Report, Error> {
+        let summary = owned::load_summary(summary, &options.columns.summary, &options.scales)?;
+        let (txns, txn_duplicates) =
+            owned::load_transactions(txns, &options.columns.txns, &options.scales)?;
+        let report = owned::validate_loaded_all(&summary, &txns, txn_duplicates, options);
+        let findings = match &report {
+            Ok(report) => &report.findings[..],
+            Err(e) => e.findings(),
+        };
+        self.replace(&summary, &txns, findings)
+            .map_err(|e| Error::store(&self.path, e))?;
+        report
+    }
+
+    fn replace(
+        &mut self,
+        summary: &Summary,
+        txns: &Ledger,
+        findings: &[Finding],
+    ) -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+        tx.execute_batch("DELETE FROM summary; DELETE FROM ledger; DELETE FROM findings;")?;
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO summary (txn_id, account, amount, currency, date, line)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )?;
+            for leg in summary.legs.iter() {
+                let partial = &leg.partial_txn;
+                insert.execute(params![
+                    partial.txn_id,
+                    partial.account,
+                    partial.amount,
+                    partial.currency,
+                    leg.date,
+                    leg.summary_line as i64,
+                ])?;
+            }
+
+            let mut insert = tx.prepare(
+                "INSERT INTO ledger (account, txn_id, amount, balance, currency, date, line)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )?;
+            let mut rows = txns.values().collect::<Vec<_>>();
+            rows.sort_by_key(|txn| txn.line);
+            for txn in rows {
+                insert.execute(params![
+                    txn.account,
+                    txn.txn_id,
+                    txn.amount,
+                    txn.balance,
+                    txn.currency,
+                    txn.date,
+                    txn.line as i64,
+                ])?;
+            }
+
+            let summary_dates = summary
+                .legs
+                .iter()
+                .map(|leg| (leg.summary_line, leg.date.as_str()))
+                .collect::<HashMap<_, _>>();
+            let posted = txns
+                .values()
+                .map(|txn| (txn.line, txn.date.as_str()))
+                .collect::<HashMap<_, _>>();
+            let mut insert = tx.prepare(
+                "INSERT INTO findings (category, severity, account, txn_id, expected, actual,
+                                       summary_line, txn_line, rate, days_to_settle, date)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            )?;
+            for finding in findings.iter() {
+                let date = match finding.summary_line {
+                    Some(line) => summary_dates.get(&line),
+                    None => finding.txn_line.and_then(|line| posted.get(&line)),
+                }
+                .filter(|date| !date.is_empty());
+                insert.execute(params![
+                    finding.category.name(),
+                    finding.severity.name(),
+                    finding.account,
+                    finding.txn_id,
+                    finding.expected,
+                    finding.actual,
+                    finding.summary_line.map(|line| line as i64),
+                    finding.txn_line.map(|line| line as i64),
+                    finding.rate.as_ref().map(ToString::to_string),
+                    finding.days_to_settle,
+                    date,
+                ])?;
+            }
+        }
+        tx.commit()
+    }
+
+    /// Run a saved report against the store.
+    pub fn query(&self, report: &SavedReport) -> Result<Table, Error> {
+        self.select(report.sql)
+            .map_err(|e| Error::store(&self.path, e))
+    }
+
+    fn select(&self, sql: &str) -> rusqlite::Result<Table> {
+        let mut statement = self.conn.prepare(sql)?;
+        let columns = statement
+            .column_names()
+            .into_iter()
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        let width = columns.len();
+        let rows = statement
+            .query_map([], |row| {
+                (0..width)
+                    .map(|i| {
+                        Ok(match row.get_ref(i)? {
+                            ValueRef::Null => String::new(),
+                            ValueRef::Integer(n) => n.to_string(),
+                            ValueRef::Real(x) => x.to_string(),
+                            ValueRef::Text(text) | ValueRef::Blob(text) => {
+                                String::from_utf8_lossy(text).into_owned()
+                            }
+                        })
+                    })
+                    .collect()
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(Table { columns, rows })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::options::DuplicatePolicy;
+    use super::*;
+
+    fn query(store: &Store, name: &str) -> Vec<Vec<String>> {
+        store.query(saved_report(name).unwrap()).unwrap().rows
+    }
+
+    fn row(values: &[&str]) -> Vec<String> {
+        values.iter().map(|&value| value.to_owned()).collect()
+    }
+
+    #[test]
+    fn saved_reports_run_against_a_loaded_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = Store::open(dir.path().join("runs.sqlite")).unwrap();
+        let report = store
+            .load(
+                "resources/summary_bad.csv",
+                "resources/txns_bad.csv",
+                &Options::default(),
+            )
+            .unwrap();
+
+        let table = store
+            .query(saved_report("findings_by_category").unwrap())
+            .unwrap();
+        assert_eq!(table.columns, row(&["category", "severity", "findings"]));
+        assert_eq!(
+            table.rows,
+            vec![
+                row(&["missing", "error", "2"]),
+                row(&["orphan", "error", "2"]),
+                row(&["amount_mismatch", "error", "1"]),
+            ]
+        );
+        assert_eq!(report.findings.len(), 5);
+        assert_eq!(
+            query(&store, "top_mismatched_accounts"),
+            vec![row(&["r", "1", "50"])]
+        );
+        // The `a:` and `y` legs, and the `a` row of `::b` and the `z` row
+        assert_eq!(
+            query(&store, "unmatched_totals"),
+            vec![
+                row(&["summary", "", "2", "250"]),
+                row(&["ledger", "", "2", "-60"])
+            ]
+        );
+    }
+
+    #[test]
+    fn read_only_stores_must_already_exist() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("runs.sqlite");
+        assert!(matches!(
+            Store::open_read_only(&path),
+            Err(Error::Store { .. })
+        ));
+        assert!(!path.exists());
+
+        Store::open(&path).unwrap();
+        let store = Store::open_read_only(&path).unwrap();
+        assert_eq!(
+            query(&store, "findings_by_category"),
+            Vec::<Vec<String>>::new()
+        );
+        assert!(store.conn.execute_batch("DELETE FROM findings").is_err());
+    }
+
+    #[test]
+    fn findings_are_dated_by_their_summary_row() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = Store::open(dir.path().join("runs.sqlite")).unwrap();
+        let ledger = dir.path().join("txns.csv");
+        let rows = std::fs::read_to_string("resources/txns_settle.csv").unwrap();
+        std::fs::write(&ledger, format!("{}g,7,5,5,2024-10-09\n", rows)).unwrap();
+        let options = Options {
+            settlement_days: Some(2),
+            ..Default::default()
+        };
+        store
+            .load("resources/summary_settle.csv", ledger.as_path(), &options)
+            .unwrap();
+        assert_eq!(
+            query(&store, "findings_by_day"),
+            vec![
+                row(&["2024-10-01", "late_settlement", "1"]),
+                row(&["2024-10-02", "late_settlement", "1"]),
+                // Dated by when it posted, since no summary row has it
+                row(&["2024-10-09", "orphan", "1"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn loading_replaces_the_previous_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("runs.sqlite");
+        let mut store = Store::open(&path).unwrap();
+        store
+            .load(
+                "resources/summary_bad.csv",
+                "resources/txns_bad.csv",
+                &Options::default(),
+            )
+            .unwrap();
+
+        // Duplicate keys fail the run, but are still stored as findings
+        let options = Options {
+            duplicates: DuplicatePolicy::Fail,
+            ..Default::default()
+        };
+        let ledger = dir.path().join("txns.csv");
+        std::fs::write(
+            &ledger,
+            "account,txn_id,amount,balance\na,1,-1,0\na,1,-1,0\n",
+        )
+        .unwrap();
+        let summary = dir.path().join("summary.csv");
+        std::fs::write(&summary, "id,from,to,amount\n").unwrap();
+        let outcome = store.load(summary.as_path(), ledger.as_path(), &options);
+        assert!(matches!(outcome, Err(Error::Duplicates(_))));
+
+        let store = Store::open_read_only(&path).unwrap();
+        assert_eq!(
+            query(&store, "findings_by_category"),
+            vec![row(&["duplicate_txn", "error", "2"])]
+        );
+        // Neither input has dates
+        assert_eq!(
+            query(&store, "findings_by_day"),
+            vec![row(&["", "duplicate_txn", "2"])]
+        );
+        assert_eq!(
+            query(&store, "top_mismatched_accounts"),
+            Vec::<Vec<String>>::new()
+        );
+    }
+}
@@ -0,0 +1,130 @@
+//! # Validation Builder
+//!
+//! The entry point for running a reconciliation in-process. Pick the
+//! inputs, a [`Validator`] and any optional checks, then either stop at the
+//! first problem with [`Validation::validate`] or collect everything with
+//! [`Validation::report`].
+//!
+
+use std::fmt;
+
+use super::error::Error;
+use super::input::Input;
+use super::options::{DuplicatePolicy, Options};
+use super::owned::Owned;
+use super::report::Report;
+use super::validator::Validator;
+
+/// A reconciliation of a summary against a transaction ledger.
+///
+/// # Example
+///
+/// ```
+/// use rust_intro::{Refs, Validation};
+///
+/// let report = Validation::new("resources/summary0.csv", "resources/txns0.csv")
+///     .strategy(&Refs)
+///     .check_balances(true)
+///     .report()
+///     .unwrap();
+///
+/// assert!(report.is_valid());
+/// assert_eq!(report.legs, 4);
+/// ```
+///
+/// Inputs don't have to be files:
+///
+/// ```
+/// use rust_intro::{Input, Validation};
+///
+/// let summary = "id,from,to,amount\n1,a,b,100\n";
+/// let txns = "account,txn_id,amount,balance\na,1,-100,0\nb,1,100,100\n";
+///
+/// Validation::new(
+///     Input::reader("summary", summary.as_bytes()),
+///     Input::reader("txns", txns.as_bytes()),
+/// )
+/// .validate()
+/// .unwrap();
+/// ```
+pub struct Validation<'v> {
+    summary: Input,
+    txns: Input,
+    strategy: &'v dyn Validator,
+    options: Options,
+}
+
+impl<'v> Validation<'v> {
+    /// A validation of `summary` against `txns`, using the [`Owned`]
+    /// strategy and default [`Options`] until told otherwise.
+    pub fn new<S: Into<Input>, T: Into<Input>>(summary: S, txns: T) -> Self {
+        Validation {
+            summary: summary.into(),
+            txns: txns.into(),
+            strategy: &Owned,
+            options: Options::default(),
+        }
+    }
+
+    /// Use a different strategy, such as one looked up with
+    /// [`strategy`](super::validator::strategy).
+    pub fn strategy(mut self, strategy: &'v dyn Validator) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Replace every optional setting at once.
+    pub fn options(mut self, options: Options) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// See [`Options::check_balances`].
+    pub fn check_balances(mut self, check_balances: bool) -> Self {
+        self.options.check_balances = check_balances;
+        self
+    }
+
+    /// See [`DuplicatePolicy`].
+    pub fn duplicates(mut self, duplicates: DuplicatePolicy) -> Self {
+        self.options.duplicates = duplicates;
+        self
+    }
+
+    /// Read the summary's `column` from the header named `header`.
+    pub fn summary_column(mut self, column: &str, header: &str) -> Self {
+        self.options.columns.summary.rename(column, header);
+        self
+    }
+
+    /// Read the ledger's `column` from the header named `header`.
+    pub fn txn_column(mut self, column: &str, header: &str) -> Self {
+        self.options.columns.txns.rename(column, header);
+        self
+    }
+
+    /// Stop at the first problem found.
+    pub fn validate(self) -> Result<(), Error> {
+        self.strategy
+            .validate(self.summary, self.txns, &self.options)
+    }
+
+    /// Walk both inputs in full and report every finding.
+    ///
+    /// Only problems that stop the inputs from being read at all, or
+    /// duplicates under [`DuplicatePolicy::Fail`], are returned as errors.
+    pub fn report(self) -> Result<Report, Error> {
+        self.strategy.report(self.summary, self.txns, &self.options)
+    }
+}
+
+impl<'v> fmt::Debug for Validation<'v> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Validation")
+            .field("summary", &self.summary)
+            .field("txns", &self.txns)
+            .field("strategy", &self.strategy.name())
+            .field("options", &self.options)
+            .finish()
+    }
+}
@@ -0,0 +1,384 @@
+//! # Validator Trait
+//!
+//! Every implementation of the reconciliation algorithm is exposed as a
+//! [`Validator`], so that callers can list them, pick one by name, or run
+//! several over the same inputs and compare the results.
+//!
+
+use super::error::Error;
+use super::input::Input;
+use super::mmap::Mmap;
+use super::options::Options;
+use super::owned::Owned;
+use super::refs::Refs;
+use super::report::Report;
+use super::sorted::Sorted;
+use super::spill::Spill;
+
+/// A strategy for reconciling a summary against a transaction ledger.
+///
+/// Implementations must agree on their results; they are free to differ in
+/// how they get there.
+pub trait Validator: Send + Sync {
+    /// The name used to select this strategy, e.g. with `--impl`.
+    fn name(&self) -> &'static str;
+
+    /// A one-line description for listings.
+    fn description(&self) -> &'static str;
+
+    /// Whether both inputs must be sorted by `txn_id`. Such a strategy only
+    /// agrees with the others on inputs that are.
+    fn needs_sorted_input(&self) -> bool {
+        false
+    }
+
+    /// Stop at the first problem found.
+    fn validate(&self, summary: Input, txns: Input, options: &Options) -> Result<(), Error>;
+
+    /// Walk both inputs in full and report every finding.
+    fn report(&self, summary: Input, txns: Input, options: &Options) -> Result<Report, Error>;
+}
+
+/// Every built-in strategy, in the order they should be listed.
+pub fn strategies() -> &'static [&'static dyn Validator] {
+    &[&Owned, &Refs, &Mmap, &Spill, &Sorted]
+}
+
+/// Look up a built-in strategy by its [`Validator::name`].
+pub fn strategy(name: &str) -> Option<&'static dyn Validator> {
+    strategies()
+        .iter()
+        .find(|validator| validator.name() == name)
+        .copied()
+}
+
+/// The names of every built-in strategy.
+pub fn strategy_names() -> Vec<&'static str> {
+    strategies()
+        .iter()
+        .map(|validator| validator.name())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::implementations::amount::Tolerance;
+    use crate::implementations::fx::Rates;
+    use crate::implementations::options::DuplicatePolicy;
+    use crate::implementations::report::{Category, Severity};
+    use proptest::prelude::*;
+
+    /// Run every strategy over the same inputs, check that their reports
+    /// are all equal and return the first.
+    fn assert_strategies_agree<S, T>(summary: S, txns: T, options: &Options) -> Report
+    where
+        S: Into<Input> + Clone,
+        T: Into<Input> + Clone,
+    {
+        let mut reports = strategies().iter().map(|validator| {
+            let report = validator
+                .report(summary.clone().into(), txns.clone().into(), options)
+                .unwrap();
+            (validator.name(), report)
+        });
+        let (first, expected) = reports.next().unwrap();
+        for (name, report) in reports {
+            assert_eq!(report, expected, "{} vs {}", name, first);
+        }
+        expected
+    }
+
+    #[test]
+    fn every_strategy_can_be_found_by_name() {
+        for validator in strategies() {
+            let found = strategy(validator.name()).expect("listed strategy not found");
+            assert_eq!(found.name(), validator.name());
+        }
+        assert!(strategy("nope").is_none());
+    }
+
+    #[test]
+    fn strategies_agree_through_the_trait() {
+        // The summary_bad fixtures aren't sorted by txn_id
+        let reports = strategies()
+            .iter()
+            .filter(|validator| !validator.needs_sorted_input())
+            .map(|validator| {
+                validator
+                    .report(
+                        "resources/summary_bad.csv".into(),
+                        "resources/txns_bad.csv".into(),
+                        &Options::default(),
+                    )
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert!(reports.windows(2).all(|pair| pair[0] == pair[1]));
+        assert!(!reports[0].is_valid());
+    }
+
+    #[test]
+    fn fields_can_be_split_on_other_delimiters() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut options = Options::default();
+        options.columns.summary.set_delimiter(b';');
+        options.columns.txns.set_delimiter(b'\t');
+        let summary = dir.path().join("summary.csv");
+        let txns = dir.path().join("txns.tsv");
+        let read = |path| std::fs::read_to_string(path).unwrap();
+        std::fs::write(
+            &summary,
+            read("resources/summary_bad.csv").replace(',', ";"),
+        )
+        .unwrap();
+        std::fs::write(&txns, read("resources/txns_bad.csv").replace(',', "\t")).unwrap();
+        let expected = Owned
+            .report(
+                "resources/summary_bad.csv".into(),
+                "resources/txns_bad.csv".into(),
+                &Options::default(),
+            )
+            .unwrap();
+        for validator in strategies()
+            .iter()
+            .filter(|validator| !validator.needs_sorted_input())
+        {
+            let report = validator
+                .report(summary.as_path().into(), txns.as_path().into(), &options)
+                .unwrap();
+            assert_eq!(report, expected, "{}", validator.name());
+        }
+    }
+
+    #[test]
+    fn transfers_too_large_to_negate_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let summary = dir.path().join("summary.csv");
+        let txns = dir.path().join("txns.csv");
+        std::fs::write(&summary, "id,from,to,amount\n1,a,b,-9223372036854775808\n").unwrap();
+        std::fs::write(&txns, "account,txn_id,amount,balance\n").unwrap();
+        let expected = format!(
+            "{}:2: column `amount`: could not parse \"-9223372036854775808\"",
+            summary.display()
+        );
+        for validator in strategies() {
+            let err = validator
+                .report(
+                    summary.as_path().into(),
+                    txns.as_path().into(),
+                    &Options::default(),
+                )
+                .unwrap_err();
+            assert_eq!(err.to_string(), expected, "{}", validator.name());
+        }
+    }
+
+    #[test]
+    fn legs_repeating_an_account_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let summary = dir.path().join("summary.csv");
+        let txns = dir.path().join("txns.csv");
+        std::fs::write(&summary, "id,account,amount\n1,a,-40\n1,b,20\n1,b,20\n").unwrap();
+        std::fs::write(
+            &txns,
+            "account,txn_id,amount,balance\na,1,-40,60\nb,1,40,40\n",
+        )
+        .unwrap();
+        for duplicates in [DuplicatePolicy::Fail, DuplicatePolicy::Warn].iter() {
+            let options = Options {
+                duplicates: *duplicates,
+                ..Default::default()
+            };
+            for validator in strategies() {
+                match validator.report(summary.as_path().into(), txns.as_path().into(), &options) {
+                    Err(Error::RepeatedLegs(findings)) => {
+                        let lines = findings
+                            .iter()
+                            .map(|finding| (finding.account.as_str(), finding.summary_line))
+                            .collect::<Vec<_>>();
+                        assert_eq!(lines, vec![("b", Some(3)), ("b", Some(4))]);
+                    }
+                    other => panic!("{}: unexpected result: {:?}", validator.name(), other),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn differences_within_tolerance_are_warnings() {
+        let mut options = Options::default();
+        options.scales.set("USD", 2);
+        let report = |options: &Options| {
+            assert_strategies_agree(
+                "resources/summary_decimal.csv",
+                "resources/txns_decimal.csv",
+                options,
+            )
+            .findings
+            .iter()
+            .map(|finding| (finding.txn_id.clone(), finding.severity))
+            .collect::<Vec<_>>()
+        };
+        let error = |id: &str| (id.to_owned(), Severity::Error);
+        let warning = |id: &str| (id.to_owned(), Severity::Warning);
+        assert_eq!(report(&options), vec![error("2"), error("3")]);
+        options.tolerance = Some(Tolerance::Absolute(1));
+        assert_eq!(report(&options), vec![warning("2"), warning("3")]);
+        options.tolerance = Some(Tolerance::Relative(0.001));
+        assert_eq!(report(&options), vec![warning("2"), error("3")]);
+    }
+
+    #[test]
+    fn legs_in_other_currencies_are_converted() {
+        let mut options = Options::default();
+        for currency in &["EUR", "USD", "GBP"] {
+            options.scales.set(*currency, 2);
+        }
+        options.rates = Rates::load("resources/rates.csv").unwrap();
+        let report = assert_strategies_agree(
+            "resources/summary_fx.csv",
+            "resources/txns_fx.csv",
+            &options,
+        );
+        let findings = &report.findings;
+        let found = findings
+            .iter()
+            .map(|finding| (finding.txn_id.as_str(), finding.category))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                ("4", Category::MissingRate),
+                ("5", Category::AmountMismatch)
+            ]
+        );
+        assert_eq!(findings[1].expected, Some(2182));
+        assert_eq!(
+            findings[1].rate.as_ref().map(|rate| rate.to_string()),
+            Some("EUR/USD@1.0912 on 2024-01-03".to_owned())
+        );
+    }
+
+    #[test]
+    fn rows_posted_after_the_window_are_late() {
+        let mut options = Options::default();
+        let report = |options: &Options| {
+            assert_strategies_agree(
+                "resources/summary_settle.csv",
+                "resources/txns_settle.csv",
+                options,
+            )
+            .findings
+            .iter()
+            .map(|finding| (finding.account.clone(), finding.days_to_settle))
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(report(&options), vec![]);
+        options.settlement_days = Some(3);
+        assert_eq!(report(&options), vec![("d".to_owned(), Some(74))]);
+        options.settlement_days = Some(2);
+        assert_eq!(
+            report(&options),
+            vec![("b".to_owned(), Some(3)), ("d".to_owned(), Some(74))]
+        );
+    }
+
+    #[test]
+    fn tolerated_differences_dont_hide_late_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let summary = dir.path().join("summary.csv");
+        let txns = dir.path().join("txns.csv");
+        std::fs::write(&summary, "id,from,to,amount,date\n1,a,b,100,2024-10-01\n").unwrap();
+        std::fs::write(
+            &txns,
+            "account,txn_id,amount,balance,date\n\
+             a,1,-100,900,2024-10-01\n\
+             b,1,99,99,2024-11-30\n",
+        )
+        .unwrap();
+        let options = Options {
+            tolerance: Some(Tolerance::Absolute(1)),
+            settlement_days: Some(2),
+            ..Default::default()
+        };
+        for validator in strategies() {
+            let report = validator
+                .report(summary.as_path().into(), txns.as_path().into(), &options)
+                .unwrap();
+            let found = report
+                .findings
+                .iter()
+                .map(|finding| (finding.category, finding.severity))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                found,
+                vec![
+                    (Category::AmountMismatch, Severity::Warning),
+                    (Category::LateSettlement, Severity::Error)
+                ],
+                "{}",
+                validator.name()
+            );
+            assert_eq!(report.matched, 1, "{}", validator.name());
+            let err = validator
+                .validate(summary.as_path().into(), txns.as_path().into(), &options)
+                .unwrap_err();
+            assert!(
+                err.to_string()
+                    .starts_with("Late settlement: [late_settlement]"),
+                "{}: {}",
+                validator.name(),
+                err
+            );
+        }
+    }
+
+    proptest! {
+        // Every case runs each strategy, spill's temporary files included
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn ids_only_match_a_row_with_the_same_account_and_txn_id(
+            debit in ("[a:]{1,4}", "[a:]{1,4}"),
+            posted in ("[a:]{1,4}", "[a:]{1,4}"),
+        ) {
+            let (account, id) = &debit;
+            let summary = format!("id,from,to,amount\n{},{},dst,5\n", id, account);
+            let mut rows = [
+                format!("{},{},-5,0\n", posted.0, posted.1),
+                format!("dst,{},5,5\n", id),
+            ];
+            // The sorted strategy needs the ledger in txn_id order
+            if posted.1 > *id {
+                rows.reverse();
+            }
+            let txns = ["account,txn_id,amount,balance\n".to_owned(), rows.concat()].concat();
+            let dir = tempfile::tempdir().unwrap();
+            let summary_path = dir.path().join("summary.csv");
+            let txns_path = dir.path().join("txns.csv");
+            std::fs::write(&summary_path, summary).unwrap();
+            std::fs::write(&txns_path, txns).unwrap();
+            let found = assert_strategies_agree(
+                summary_path.as_path(),
+                txns_path.as_path(),
+                &Options::default(),
+            )
+            .findings
+                .iter()
+                .map(|finding| (finding.category, finding.account.clone()))
+                .collect::<Vec<_>>();
+            if debit == posted {
+                prop_assert_eq!(found, vec![]);
+            } else {
+                prop_assert_eq!(
+                    found,
+                    vec![
+                        (Category::Missing, account.clone()),
+                        (Category::Orphan, posted.0.clone()),
+                    ]
+                );
+            }
+        }
+    }
+}
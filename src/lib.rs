@@ -14,12 +14,34 @@
 //!
 //! Let's take a deeper look at some of the [`benefits`].
 //!
-
+//! ## Reconciliation
+//!
+//! The crate also ships the CSV reconciliation engine used by the
+//! `rust_intro` binary, so it can be called in-process. Start with
+//! [`Validation`], or see [`implementations`] for the details.
+//!
 
 pub mod benefits;
+pub mod implementations;
 pub mod syntax;
 pub mod uses;
 
+pub use implementations::amount::{Scales, Tolerance};
+pub use implementations::columns::{Columns, Mapping};
+pub use implementations::error::Error;
+pub use implementations::fx::{Rate, Rates};
+pub use implementations::input::Input;
+pub use implementations::mmap::Mmap;
+pub use implementations::options::{DuplicatePolicy, Options};
+pub use implementations::output::{Format, Run, Status};
+pub use implementations::report::{Category, Finding, Report, Severity, SummaryRow};
+pub use implementations::owned::Owned;
+pub use implementations::refs::Refs;
+pub use implementations::sorted::Sorted;
+pub use implementations::spill::Spill;
+pub use implementations::store::Store;
+pub use implementations::{strategies, strategy, Validation, Validator};
+
 #[cfg(test)]
 pub mod tests {
     #[test]
@@ -1,35 +1,958 @@
-use clap::{App, Arg, ArgMatches};
+use clap::{App, AppSettings, Arg, ArgMatches, Shell, SubCommand};
+use serde_json::json;
 use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant};
 
-mod implementations;
+use rust_intro::implementations::amount::MAX_SCALE;
+use rust_intro::implementations::columns::{SUMMARY_RENAMES, TXN_RENAMES};
+use rust_intro::implementations::config::{Config, Profile};
+use rust_intro::implementations::index::LedgerIndex;
+use rust_intro::implementations::store::{self, REPORTS};
+use rust_intro::{
+    Columns, DuplicatePolicy, Format, Mapping, Owned, Rates, Report, Run, Scales, Status, Store,
+    Tolerance, Validation, Validator,
+};
 
-use implementations::owned;
-use implementations::refs;
+/// What gets written besides the outcome of a run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Verbosity {
+    /// Only errors, and the exit status
+    Quiet,
+    Normal,
+    /// Also how long each step took, and what was written where
+    Verbose,
+}
+
+impl Verbosity {
+    fn from_args(args: &ArgMatches) -> Self {
+        match (args.is_present("quiet"), args.is_present("verbose")) {
+            (true, _) => Verbosity::Quiet,
+            (false, false) => Verbosity::Normal,
+            (false, true) => Verbosity::Verbose,
+        }
+    }
+
+    /// Note that a file was written to `path`.
+    fn wrote<P: AsRef<Path>>(self, path: P) {
+        if self == Verbosity::Verbose {
+            eprintln!("wrote {}", path.as_ref().display());
+        }
+    }
+}
+
+/// The config file read when a profile is given without `--config`.
+const DEFAULT_CONFIG: &str = "rust_intro.toml";
+
+/// Every subcommand of [`app`], in the order they're listed.
+const SUBCOMMANDS: [&str; 7] = [
+    "validate",
+    "generate",
+    "index",
+    "report",
+    "bench",
+    "completions",
+    "man",
+];
+
+/// The whole command line, with a subcommand for each thing the binary
+/// can do. `report_help` lists the saved reports.
+fn app<'a>(
+    strategies: &'a [&'static str],
+    reports: &'a [&'static str],
+    report_help: &'a str,
+) -> App<'a, 'a> {
+    App::new("rust_intro")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Reconcile a summary of transfers against the ledger they should have produced")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .setting(AppSettings::VersionlessSubcommands)
+        .arg(
+            Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .global(true)
+                .conflicts_with("quiet")
+                .help("Also say how long each step took, and what was written where"),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .short("q")
+                .long("quiet")
+                .global(true)
+                .help("Only print errors; the exit status tells the outcome"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .takes_value(true)
+                .global(true)
+                .value_name("FILE")
+                .help("Config file of named profiles; rust_intro.toml if a profile is given without one"),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .takes_value(true)
+                .global(true)
+                .value_name("NAME")
+                .help("Profile from the config file to take settings from; flags given override it"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .global(true)
+                .possible_values(&Format::NAMES)
+                .help("Output format, text by default; json always lists every finding"),
+        )
+        .subcommand(validate_command(strategies))
+        .subcommand(generate_command())
+        .subcommand(index_command())
+        .subcommand(report_command(reports, report_help))
+        .subcommand(bench_command(strategies))
+        .subcommand(completions_command())
+        .subcommand(man_command())
+}
+
+fn validate_command<'a>(strategies: &'a [&'static str]) -> App<'a, 'a> {
+    SubCommand::with_name("validate")
+        .about("Check a summary against a transaction ledger")
+        .arg(
+            Arg::with_name("SUMMARY")
+                .required_unless("list_impls")
+                .index(1),
+        )
+        .arg(
+            Arg::with_name("TRANSACTIONS")
+                .required_unless("list_impls")
+                .index(2),
+        )
+        .arg(
+            Arg::with_name("implementation")
+                .long("impl")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .use_delimiter(true)
+                .default_value("owned")
+                .possible_values(strategies)
+                .help("Strategy to run; give several, comma separated, to run them side by side"),
+        )
+        .arg(
+            Arg::with_name("list_impls")
+                .long("list-impls")
+                .help("List the available strategies and exit"),
+        )
+        .arg(
+            Arg::with_name("all")
+                .long("all")
+                .help("Report every discrepancy instead of stopping at the first"),
+        )
+        .arg(
+            Arg::with_name("check_balances")
+                .long("check-balances")
+                .help("Verify each account's running balance in ledger order"),
+        )
+        .arg(
+            Arg::with_name("duplicates")
+                .long("duplicates")
+                .takes_value(true)
+                .default_value("fail")
+                .possible_values(&DuplicatePolicy::NAMES)
+                .help(
+                    "Whether repeated keys in either file fail the run or are reported as warnings",
+                ),
+        )
+        .arg(
+            Arg::with_name("columns")
+                .long("columns")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("CSV of column renames, with the header input,column,header"),
+        )
+        .arg(
+            Arg::with_name("summary_column")
+                .long("summary-column")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("COLUMN=HEADER")
+                .validator(|spec| check_rename(&spec, &SUMMARY_RENAMES))
+                .help("Read a summary column from a differently named header"),
+        )
+        .arg(
+            Arg::with_name("txn_column")
+                .long("txn-column")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("COLUMN=HEADER")
+                .validator(|spec| check_rename(&spec, &TXN_RENAMES))
+                .help("Read a transactions column from a differently named header"),
+        )
+        .arg(
+            Arg::with_name("scale")
+                .long("scale")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("[CURRENCY=]PLACES")
+                .validator(|spec| split_scale(&spec).map(|_| ()))
+                .help("Decimal places in amounts, for one currency or, without one, by default"),
+        )
+        .arg(
+            Arg::with_name("tolerance")
+                .long("tolerance")
+                .takes_value(true)
+                .value_name("UNITS|PERCENT%")
+                .validator(|tolerance| tolerance.parse::<Tolerance>().map(|_| ()))
+                .help("Report amounts this close to the summary's as warnings, e.g. 1 or 0.01%"),
+        )
+        .arg(
+            Arg::with_name("rates")
+                .long("rates")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("CSV of exchange rates, with the header date,pair,rate"),
+        )
+        .arg(
+            Arg::with_name("settlement_days")
+                .long("settlement-days")
+                .takes_value(true)
+                .value_name("DAYS")
+                .validator(|days| days.parse::<u32>().map(|_| ()).map_err(|e| e.to_string()))
+                .help("Report ledger rows dated more than this many days after their summary row"),
+        )
+        .arg(
+            Arg::with_name("memory_budget")
+                .long("memory-budget")
+                .takes_value(true)
+                .value_name("SIZE")
+                .validator(|size| parse_size(&size).map(|_| ()))
+                .help("Memory the spill implementation may use, e.g. 512M or 4G"),
+        )
+        .arg(
+            Arg::with_name("spill_dir")
+                .long("spill-dir")
+                .takes_value(true)
+                .value_name("DIR")
+                .help("Where the spill implementation writes its partitions"),
+        )
+        .arg(
+            Arg::with_name("index")
+                .long("index")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Keep an index of the ledger here between runs, so only new rows are parsed")
+                .long_help(
+                    "Keep an index of the ledger here between runs, so only new rows are parsed.\n\
+                     \n\
+                     The index assumes the ledger is only ever appended to. Each run still reads \
+                     the whole index file back into memory, which grows with the number of keys \
+                     in the ledger. Only the last 64 KiB of the indexed bytes are checksummed, so \
+                     an edit to older rows that leaves the ledger's length and those bytes as \
+                     they were goes unnoticed: run `index --rebuild` after rewriting a ledger.",
+                ),
+        )
+        .arg(
+            Arg::with_name("store")
+                .long("store")
+                .takes_value(true)
+                .value_name("FILE")
+                .conflicts_with("index")
+                .help("Also load both inputs and every finding into this SQLite file"),
+        )
+        .arg(
+            Arg::with_name("junit")
+                .long("junit")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Also write the findings as JUnit XML, one test case per summary row"),
+        )
+        .arg(
+            Arg::with_name("discrepancies")
+                .long("discrepancies")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Also write the findings as a CSV of discrepancies"),
+        )
+        .after_help(
+            "EXIT STATUS:\n    0    the inputs reconcile\n    \
+             1    one or more findings\n    2    the inputs could not be read",
+        )
+}
+
+fn generate_command<'a>() -> App<'a, 'a> {
+    SubCommand::with_name("generate")
+        .about("Write a random summary and the ledger it reconciles with")
+        .arg(
+            Arg::with_name("NUM_ACCTS")
+                .required(true)
+                .index(1)
+                .validator(|n| n.parse::<u32>().map(|_| ()).map_err(|e| e.to_string()))
+                .help("How many accounts send transfers"),
+        )
+        .arg(
+            Arg::with_name("TXNS_PER_ACCT")
+                .required(true)
+                .index(2)
+                .validator(|n| n.parse::<u32>().map(|_| ()).map_err(|e| e.to_string()))
+                .help("How many transfers each of them sends"),
+        )
+        .arg(
+            Arg::with_name("dir")
+                .long("dir")
+                .takes_value(true)
+                .value_name("DIR")
+                .default_value("resources")
+                .help("Where to write summary<N>.csv and txns<N>.csv"),
+        )
+}
 
-pub fn get_args<'a>() -> ArgMatches<'a> {
-    App::new("csv_parser")
+fn index_command<'a>() -> App<'a, 'a> {
+    SubCommand::with_name("index")
+        .about("Show how much of a ledger an index written with `validate --index` covers")
+        .arg(Arg::with_name("TRANSACTIONS").required(true).index(1))
+        .arg(Arg::with_name("INDEX").required(true).index(2))
+        .arg(
+            Arg::with_name("rebuild")
+                .long("rebuild")
+                .help("Remove the index, so the next run indexes the whole ledger again"),
+        )
+}
+
+fn report_command<'a>(reports: &'a [&'static str], report_help: &'a str) -> App<'a, 'a> {
+    SubCommand::with_name("report")
+        .alias("query")
+        .about("Run a saved report against a store written with `validate --store`")
+        .arg(Arg::with_name("STORE").required(true).index(1))
+        .arg(
+            Arg::with_name("REPORT")
+                .required(true)
+                .index(2)
+                .possible_values(reports),
+        )
+        .after_help(report_help)
+}
+
+fn bench_command<'a>(strategies: &'a [&'static str]) -> App<'a, 'a> {
+    SubCommand::with_name("bench")
+        .about("Time each strategy over the same inputs, in process")
         .arg(Arg::with_name("SUMMARY").required(true).index(1))
         .arg(Arg::with_name("TRANSACTIONS").required(true).index(2))
         .arg(
             Arg::with_name("implementation")
                 .long("impl")
                 .takes_value(true)
-                .default_value("owned")
-                .possible_values(&["owned", "refs"]),
+                .multiple(true)
+                .number_of_values(1)
+                .use_delimiter(true)
+                .possible_values(strategies)
+                .help("Strategies to time, comma separated; all of them by default"),
+        )
+        .arg(
+            Arg::with_name("runs")
+                .long("runs")
+                .takes_value(true)
+                .default_value("5")
+                .validator(|n| match n.parse::<u32>() {
+                    Ok(0) | Err(_) => Err(format!("expected a number of runs, got `{}`", n)),
+                    Ok(_) => Ok(()),
+                })
+                .help("How many times to run each strategy"),
+        )
+        .arg(
+            Arg::with_name("all")
+                .long("all")
+                .help("Time full reports instead of stopping at the first problem"),
+        )
+}
+
+fn completions_command<'a>() -> App<'a, 'a> {
+    SubCommand::with_name("completions")
+        .about("Print a shell completion script")
+        .arg(
+            Arg::with_name("SHELL")
+                .required(true)
+                .index(1)
+                .possible_values(&Shell::variants()),
+        )
+}
+
+fn man_command<'a>() -> App<'a, 'a> {
+    SubCommand::with_name("man")
+        .about("Write a man page for the binary and each of its subcommands")
+        .arg(
+            Arg::with_name("dir")
+                .long("dir")
+                .takes_value(true)
+                .value_name("DIR")
+                .default_value(".")
+                .help("Where to write the pages"),
         )
-        .get_matches()
 }
 
-fn main() -> Result<(), Box<Error>> {
-    let args = get_args();
+/// Parse the command line, exiting with [`Status::Failed`] if it's wrong.
+fn get_args<'a>(app: App<'a, 'a>) -> ArgMatches<'a> {
+    app.get_matches_safe().unwrap_or_else(|e| {
+        if !e.use_stderr() {
+            e.exit()
+        }
+        eprintln!("{}", e.message);
+        process::exit(Status::Failed.exit_code())
+    })
+}
+
+/// Parse a size in bytes, with an optional K, M or G suffix.
+fn parse_size(size: &str) -> Result<u64, String> {
+    let (digits, scale) = match size.char_indices().last() {
+        Some((i, 'K')) | Some((i, 'k')) => (&size[..i], 1 << 10),
+        Some((i, 'M')) | Some((i, 'm')) => (&size[..i], 1 << 20),
+        Some((i, 'G')) | Some((i, 'g')) => (&size[..i], 1 << 30),
+        _ => (size, 1),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(scale))
+        .ok_or_else(|| format!("expected a size such as 512M, got `{}`", size))
+}
+
+/// Split a `COLUMN=HEADER` rename.
+fn split_rename(spec: &str) -> Option<(&str, &str)> {
+    let mut parts = spec.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(column), Some(header)) if !column.is_empty() && !header.is_empty() => {
+            Some((column, header))
+        }
+        _ => None,
+    }
+}
+
+fn check_rename(spec: &str, known: &[&str]) -> Result<(), String> {
+    match split_rename(spec) {
+        Some((column, _)) if known.contains(&column) => Ok(()),
+        Some((column, _)) => Err(format!(
+            "unknown column `{}`, expected one of: {}",
+            column,
+            known.join(", ")
+        )),
+        None => Err(format!("expected COLUMN=HEADER, got `{}`", spec)),
+    }
+}
+
+/// Split a `[CURRENCY=]PLACES` scale, with an empty currency for the
+/// default.
+fn split_scale(spec: &str) -> Result<(&str, u32), String> {
+    let (currency, places) = match spec.rfind('=') {
+        Some(at) if at > 0 => (&spec[..at], &spec[at + 1..]),
+        Some(_) => return Err(format!("expected [CURRENCY=]PLACES, got `{}`", spec)),
+        None => ("", spec),
+    };
+    match places.parse::<u32>() {
+        Ok(places) if places <= MAX_SCALE => Ok((currency, places)),
+        _ => Err(format!(
+            "expected up to {} decimal places, got `{}`",
+            MAX_SCALE, places
+        )),
+    }
+}
+
+/// Apply every `[CURRENCY=]PLACES` given with `--scale` to `scales`.
+fn apply_scales(args: &ArgMatches, scales: &mut Scales) {
+    for (currency, places) in args
+        .values_of("scale")
+        .into_iter()
+        .flatten()
+        .filter_map(|spec| split_scale(spec).ok())
+    {
+        match currency {
+            "" => scales.set_default(places),
+            currency => scales.set(currency, places),
+        }
+    }
+}
+
+/// Apply every `COLUMN=HEADER` given for `arg` to `columns`.
+fn apply_renames(args: &ArgMatches, arg: &str, columns: &mut Columns) {
+    for (column, header) in args
+        .values_of(arg)
+        .into_iter()
+        .flatten()
+        .filter_map(split_rename)
+    {
+        columns.rename(column, header);
+    }
+}
+
+fn main() {
+    let names = rust_intro::implementations::validator::strategy_names();
+    let reports = REPORTS.iter().map(|report| report.name).collect::<Vec<_>>();
+    let report_help = REPORTS
+        .iter()
+        .map(|report| format!("    {:<24} {}", report.name, report.description))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let report_help = format!("REPORTS:\n{}", report_help);
+    let build = || app(&names, &reports, &report_help);
+    let args = get_args(build());
+    let code = match run(&args, build) {
+        Ok(status) => status.exit_code(),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            Status::Failed.exit_code()
+        }
+    };
+    process::exit(code)
+}
+
+fn run<'a, F>(args: &ArgMatches, build: F) -> Result<Status, Box<dyn Error>>
+where
+    F: Fn() -> App<'a, 'a>,
+{
+    let (name, args) = match args.subcommand() {
+        (name, Some(args)) => (name, args),
+        _ => return Err("No subcommand".into()),
+    };
+    // Load the config up front, so that it's checked whatever runs
+    let profile = profile(args)?.unwrap_or_default();
+    match (name, args) {
+        ("validate", args) => validate(args, profile),
+        ("generate", args) => generate(args),
+        ("index", args) => index(args),
+        ("report", args) => report(args),
+        ("bench", args) => bench(args, profile),
+        ("completions", args) => completions(args, build()),
+        ("man", args) => man(args, build),
+        _ => Err("No subcommand".into()),
+    }
+}
+
+/// The profile picked with `--profile`, from the config file given with
+/// `--config` or the default one. A config given without a profile is
+/// still checked.
+fn profile(args: &ArgMatches) -> Result<Option<Profile>, Box<dyn Error>> {
+    let path = match (args.value_of("config"), args.is_present("profile")) {
+        (Some(path), _) => path,
+        (None, true) => DEFAULT_CONFIG,
+        (None, false) => return Ok(None),
+    };
+    let config = Config::load(path)?;
+    match args.value_of("profile") {
+        Some(name) => Ok(Some(config.profile(name)?.clone())),
+        None => Ok(None),
+    }
+}
+
+/// The format given with `--format`, or failing that `default`.
+fn format(args: &ArgMatches, default: Option<Format>) -> Result<Format, Box<dyn Error>> {
+    match args.value_of("format") {
+        Some(name) => Ok(Format::from_name(name).ok_or("Bad format")?),
+        None => Ok(default.unwrap_or_default()),
+    }
+}
+
+/// The strategies given with `--impl`, or failing that those named by the
+/// profile, or failing that `default`.
+fn strategies(
+    args: &ArgMatches,
+    profile: &Profile,
+    default: &[&'static dyn Validator],
+) -> Result<Vec<&'static dyn Validator>, Box<dyn Error>> {
+    let names = match args.occurrences_of("implementation") {
+        0 if !profile.strategies.is_empty() => profile.strategies.clone(),
+        0 => return Ok(default.to_vec()),
+        _ => args
+            .values_of("implementation")
+            .into_iter()
+            .flatten()
+            .collect(),
+    };
+    Ok(names
+        .into_iter()
+        .map(|name| rust_intro::strategy(name).ok_or("Bad implementation"))
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+fn validate(args: &ArgMatches, profile: Profile) -> Result<Status, Box<dyn Error>> {
+    let verbosity = Verbosity::from_args(args);
+    if args.is_present("list_impls") {
+        for strategy in rust_intro::strategies() {
+            println!("{:<8} {}", strategy.name(), strategy.description());
+        }
+        return Ok(Status::Valid);
+    }
+    let summary_path = args.value_of("SUMMARY").ok_or("No summary path")?;
+    let txns_path = args.value_of("TRANSACTIONS").ok_or("No transaction path")?;
+    let strategies = strategies(args, &profile, &[&Owned])?;
+    let format = format(args, profile.output.format)?;
+    let output = profile.output;
+    let junit = args.value_of("junit").map(PathBuf::from).or(output.junit);
+    let discrepancies = args
+        .value_of("discrepancies")
+        .map(PathBuf::from)
+        .or(output.discrepancies);
+
+    // Whatever is given on the command line wins over the profile
+    let mut options = profile.options;
+    options.check_balances |= args.is_present("check_balances");
+    if args.occurrences_of("duplicates") > 0 {
+        options.duplicates = args
+            .value_of("duplicates")
+            .and_then(DuplicatePolicy::from_name)
+            .ok_or("Bad duplicate policy")?;
+    }
+    options.record_rows = junit.is_some();
+    options.memory_budget = args.value_of("memory_budget").map(parse_size).transpose()?;
+    options.spill_dir = args.value_of("spill_dir").map(PathBuf::from);
+    options.ledger_index = args.value_of("index").map(PathBuf::from);
+    apply_scales(args, &mut options.scales);
+    if let Some(tolerance) = args.value_of("tolerance") {
+        options.tolerance = Some(tolerance.parse()?);
+    }
+    if let Some(days) = args.value_of("settlement_days") {
+        options.settlement_days = Some(days.parse()?);
+    }
+    if let Some(path) = args.value_of("columns") {
+        let mapping = Mapping::load(path)?;
+        options.columns.summary.merge(mapping.summary);
+        options.columns.txns.merge(mapping.txns);
+    }
+    if let Some(path) = args.value_of("rates") {
+        options.rates = Rates::load(path)?;
+    }
+    if options.ledger_index.is_some() && strategies.iter().any(|s| s.name() != "owned") {
+        return Err("only the owned implementation can use a ledger index".into());
+    }
+    if args.is_present("store") && strategies.iter().any(|s| s.name() != "owned") {
+        return Err("only the owned implementation can load a store".into());
+    }
+    apply_renames(args, "summary_column", &mut options.columns.summary);
+    apply_renames(args, "txn_column", &mut options.columns.txns);
+    let validation = |strategy: &'static dyn Validator| {
+        Validation::new(summary_path, txns_path)
+            .strategy(strategy)
+            .options(options.clone())
+    };
+
+    if strategies.len() > 1 {
+        if format != Format::Text {
+            return Err("only text output can compare implementations".into());
+        }
+        if summary_path == "-" || txns_path == "-" {
+            return Err("stdin can only be read by one implementation".into());
+        }
+        return compare(
+            &strategies,
+            args.is_present("all") || output.all,
+            validation,
+        );
+    }
+
+    // Report documents need the row counts, which only a full run has
+    let all = args.is_present("all")
+        || output.all
+        || args.is_present("store")
+        || format == Format::Json
+        || junit.is_some()
+        || discrepancies.is_some();
+    let strategy = strategies[0];
+    let validation = validation(strategy);
+    let start = Instant::now();
+    let outcome = if let Some(path) = args.value_of("store") {
+        Store::open(path).and_then(|mut store| store.load(summary_path, txns_path, &options))
+    } else if all {
+        validation.report()
+    } else {
+        validation.validate().map(|_| Report::default())
+    };
+    let run = Run {
+        summary: Path::new(summary_path),
+        txns: Path::new(txns_path),
+        strategy: strategy.name(),
+        elapsed: start.elapsed(),
+        outcome,
+    };
+    match format {
+        Format::Json => println!("{}", run.to_json()),
+        Format::Text => match &run.outcome {
+            Ok(report) if all && verbosity > Verbosity::Quiet => println!("{}", report),
+            Ok(_) => {}
+            Err(e) => eprintln!("error: {}", e),
+        },
+    }
+    if verbosity == Verbosity::Verbose {
+        eprintln!("{}: {:?}", run.strategy, run.elapsed);
+    }
+    if let Some(path) = junit {
+        fs::write(&path, run.to_junit())?;
+        verbosity.wrote(path);
+    }
+    if let Some(path) = discrepancies {
+        fs::write(&path, run.to_discrepancy_csv())?;
+        verbosity.wrote(path);
+    }
+    Ok(run.status())
+}
+
+fn generate(args: &ArgMatches) -> Result<Status, Box<dyn Error>> {
+    let verbosity = Verbosity::from_args(args);
+    let number = |arg| -> Result<u32, Box<dyn Error>> {
+        Ok(args.value_of(arg).ok_or("bad num")?.parse::<u32>()?)
+    };
+    let dir = Path::new(args.value_of("dir").ok_or("No directory")?);
+    let (summary, txns) = rust_intro::implementations::generate::generate(
+        dir,
+        number("NUM_ACCTS")?,
+        number("TXNS_PER_ACCT")?,
+    )?;
+    verbosity.wrote(&summary);
+    verbosity.wrote(&txns);
+    Ok(Status::Valid)
+}
+
+/// Print how much of the ledger an index covers, or remove it.
+fn index(args: &ArgMatches) -> Result<Status, Box<dyn Error>> {
+    let verbosity = Verbosity::from_args(args);
+    let ledger = Path::new(args.value_of("TRANSACTIONS").ok_or("No transaction path")?);
+    let index_path = Path::new(args.value_of("INDEX").ok_or("No index path")?);
+    if args.is_present("rebuild") {
+        match fs::remove_file(index_path) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            removed => removed?,
+        }
+        if verbosity == Verbosity::Verbose {
+            eprintln!("removed {}", index_path.display());
+        }
+        return Ok(Status::Valid);
+    }
+    let ledger_index = LedgerIndex::load(index_path, ledger)?;
+    let bytes = fs::metadata(ledger)?.len();
+    let indexed = ledger_index.indexed_bytes();
+    match format(args, None)? {
+        Format::Json => println!(
+            "{}",
+            json!({
+                "ledger": ledger.display().to_string(),
+                "bytes": bytes,
+                "indexed_bytes": indexed,
+                "indexed_rows": ledger_index.rows(),
+            })
+        ),
+        Format::Text if verbosity > Verbosity::Quiet => {
+            println!("ledger: {} ({} bytes)", ledger.display(), bytes);
+            println!("indexed rows: {}", ledger_index.rows());
+            println!("indexed bytes: {}", indexed);
+            println!("appended since: {} bytes", bytes.saturating_sub(indexed));
+        }
+        Format::Text => {}
+    }
+    Ok(Status::Valid)
+}
+
+/// Print a saved report's rows from a store, as CSV or as JSON objects.
+fn report(args: &ArgMatches) -> Result<Status, Box<dyn Error>> {
+    let path = args.value_of("STORE").ok_or("No store path")?;
+    let report = args
+        .value_of("REPORT")
+        .and_then(store::saved_report)
+        .ok_or("Bad report")?;
+    if !Path::new(path).exists() {
+        return Err(format!("{}: no such store", path).into());
+    }
+    let table = Store::open_read_only(path)?.query(report)?;
+    if format(args, None)? == Format::Json {
+        let rows = table
+            .rows
+            .iter()
+            .map(|row| {
+                table
+                    .columns
+                    .iter()
+                    .cloned()
+                    .zip(row.iter().map(|value| json!(value)))
+                    .collect()
+            })
+            .collect::<Vec<serde_json::Map<_, _>>>();
+        println!("{}", serde_json::Value::from(rows));
+        return Ok(Status::Valid);
+    }
+    let mut writer = csv::Writer::from_writer(io::stdout());
+    writer.write_record(&table.columns)?;
+    for row in table.rows.iter() {
+        writer.write_record(row)?;
+    }
+    writer.flush()?;
+    Ok(Status::Valid)
+}
+
+/// Run each strategy over the same inputs a number of times, and print
+/// the fastest and mean run of each.
+fn bench(args: &ArgMatches, profile: Profile) -> Result<Status, Box<dyn Error>> {
+    let verbosity = Verbosity::from_args(args);
     let summary_path = args.value_of("SUMMARY").ok_or("No summary path")?;
     let txns_path = args.value_of("TRANSACTIONS").ok_or("No transaction path")?;
-    if let Some(implementation) = args.value_of("implementation") {
-        match implementation {
-            "owned" => return owned::validate(summary_path, txns_path).map(|_| ()),
-            "refs" => return refs::validate(summary_path, txns_path).map(|_| ()),
-            _ => return Err("Bad implementation".into()),
+    if summary_path == "-" || txns_path == "-" {
+        return Err("stdin can only be read once".into());
+    }
+    let strategies = strategies(args, &profile, rust_intro::strategies())?;
+    let runs = args.value_of("runs").ok_or("No runs")?.parse::<u32>()?;
+    let all = args.is_present("all");
+    let mut timings = Vec::new();
+    for strategy in strategies {
+        let mut elapsed = Vec::new();
+        for _ in 0..runs {
+            let validation = Validation::new(summary_path, txns_path)
+                .strategy(strategy)
+                .options(profile.options.clone());
+            let start = Instant::now();
+            let outcome = match all {
+                true => validation.report().map(|_| ()),
+                false => validation.validate(),
+            };
+            elapsed.push(start.elapsed());
+            // Findings are fine to time, inputs that can't be read are not
+            if let Err(e) = outcome {
+                if e.findings().is_empty() {
+                    return Err(e.into());
+                }
+            }
+            if verbosity == Verbosity::Verbose {
+                eprintln!("{}: {:?}", strategy.name(), start.elapsed());
+            }
+        }
+        let fastest = elapsed.iter().min().copied().unwrap_or_default();
+        let mean = elapsed.iter().sum::<Duration>() / runs;
+        timings.push((strategy.name(), fastest, mean));
+    }
+    match format(args, profile.output.format)? {
+        Format::Json => {
+            let timings = timings
+                .iter()
+                .map(|(name, fastest, mean)| {
+                    json!({
+                        "strategy": name,
+                        "runs": runs,
+                        "fastest_ms": fastest.as_secs_f64() * 1000.0,
+                        "mean_ms": mean.as_secs_f64() * 1000.0,
+                    })
+                })
+                .collect::<Vec<_>>();
+            println!("{}", serde_json::Value::from(timings));
+        }
+        Format::Text if verbosity > Verbosity::Quiet => {
+            for (name, fastest, mean) in timings {
+                println!(
+                    "{:<8} fastest {:>10.2?}  mean {:>10.2?}",
+                    name, fastest, mean
+                );
+            }
+        }
+        Format::Text => {}
+    }
+    Ok(Status::Valid)
+}
+
+fn completions(args: &ArgMatches, mut app: App) -> Result<Status, Box<dyn Error>> {
+    let shell = args.value_of("SHELL").ok_or("No shell")?.parse::<Shell>()?;
+    app.gen_completions_to("rust_intro", shell, &mut io::stdout());
+    Ok(Status::Valid)
+}
+
+/// Write `rust_intro.1`, and a `rust_intro-<subcommand>.1` page for each
+/// subcommand, with each page's text taken from the matching `--help`.
+fn man<'a, F>(args: &ArgMatches, build: F) -> Result<Status, Box<dyn Error>>
+where
+    F: Fn() -> App<'a, 'a>,
+{
+    let verbosity = Verbosity::from_args(args);
+    let dir = Path::new(args.value_of("dir").ok_or("No directory")?);
+    let pages = std::iter::once(None).chain(SUBCOMMANDS.iter().map(Some));
+    for subcommand in pages {
+        let argv = std::iter::once("rust_intro")
+            .chain(subcommand.copied())
+            .chain(std::iter::once("--help"));
+        let help = match build().get_matches_from_safe(argv) {
+            Err(e) if e.kind == clap::ErrorKind::HelpDisplayed => e.message,
+            _ => return Err("could not render the help".into()),
+        };
+        // The help starts with the command's name, then what it's about
+        let about = help.lines().nth(1).unwrap_or_default();
+        let name = match subcommand {
+            Some(subcommand) => format!("rust_intro-{}", subcommand),
+            None => "rust_intro".to_owned(),
+        };
+        let path = dir.join(format!("{}.1", name));
+        fs::write(&path, man_page(&name, about, &help))?;
+        verbosity.wrote(&path);
+    }
+    Ok(Status::Valid)
+}
+
+/// A man page in roff, with `help` as its description.
+fn man_page(name: &str, about: &str, help: &str) -> String {
+    let escape = |line: &str| {
+        let line = line.replace('\\', "\\e");
+        match line.starts_with('.') || line.starts_with('\'') {
+            true => format!("\\&{}", line),
+            false => line,
         }
     };
-    Err("Bad arguments".into())
+    let mut page = format!(
+        ".TH {} 1 \"\" \"{} {}\"\n.SH NAME\n{} \\- {}\n.SH DESCRIPTION\n.nf\n",
+        name.to_uppercase(),
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        name,
+        escape(about),
+    );
+    for line in help.lines() {
+        page.push_str(&escape(line));
+        page.push('\n');
+    }
+    page.push_str(".fi\n");
+    page
+}
+
+/// Run each strategy over the same inputs, print what each of them made of
+/// it, and fail if they don't all agree.
+fn compare<F>(
+    strategies: &[&'static dyn Validator],
+    all: bool,
+    validation: F,
+) -> Result<Status, Box<dyn Error>>
+where
+    F: Fn(&'static dyn Validator) -> Validation<'static>,
+{
+    let outcomes = strategies
+        .iter()
+        .map(|strategy| {
+            let outcome = if all {
+                validation(*strategy).report()
+            } else {
+                validation(*strategy).validate().map(|_| Report::default())
+            };
+            outcome.map_err(|e| (e.findings().is_empty(), e.to_string()))
+        })
+        .collect::<Vec<_>>();
+    for (strategy, outcome) in strategies.iter().zip(outcomes.iter()) {
+        println!("== {} ==", strategy.name());
+        match outcome {
+            Ok(report) if all => println!("{}", report),
+            Ok(_) => println!("valid"),
+            Err((_, e)) => println!("error: {}", e),
+        }
+    }
+    if outcomes.windows(2).any(|pair| pair[0] != pair[1]) {
+        return Err("strategies disagree".into());
+    }
+    Ok(match &outcomes[0] {
+        Ok(report) if report.is_valid() => Status::Valid,
+        Ok(_) | Err((false, _)) => Status::Findings,
+        Err((true, _)) => Status::Failed,
+    })
 }
@@ -25,7 +25,7 @@
 /// use rust_intro::syntax;
 ///
 /// // This allows us to call functions defined in that namespace
-/// syntax::a_hello_world();
+/// syntax::hello_world();
 /// ```
 pub fn hello_world() {
     println!("Hello, world!")